@@ -1,6 +1,6 @@
 use crate::error::HarvestResult;
 use harvest_ir::fs::RawDir;
-use harvest_ir::HarvestIR;
+use harvest_ir::{HarvestIR, NotUnique};
 use std::path::PathBuf;
 
 use harvest_translate::tools::load_raw_source::RawSource;
@@ -10,48 +10,23 @@ use harvest_translate::tools::try_cargo_build::CargoBuildResult;
 /// Extract a single CargoPackage representation from the IR.
 /// Returns an error if there are 0 or multiple CargoPackage representations.
 pub fn raw_cargo_package(ir: &HarvestIR) -> HarvestResult<&RawDir> {
-    let cargo_packages: Vec<&RawDir> = ir
-        .get_by_representation::<CargoPackage>()
-        .map(|(_, r)| &r.dir)
-        .collect();
-
-    match cargo_packages.len() {
-        0 => Err("No CargoPackage representation found in IR".into()),
-        1 => Ok(cargo_packages[0]),
-        n => Err(format!(
-            "Found {} CargoPackage representations, expected at most 1",
-            n
-        )
-        .into()),
-    }
+    Ok(&ir.expect_unique::<CargoPackage>()?.1.dir)
 }
 
 /// Extract a single RawSource representation from the IR.
 /// Returns an error if there are 0 or multiple RawSource representations.
 pub fn raw_source(ir: &HarvestIR) -> HarvestResult<&RawDir> {
-    let raw_sources: Vec<&RawDir> = ir
-        .get_by_representation::<RawSource>()
-        .map(|(_, r)| &r.dir)
-        .collect();
-
-    match raw_sources.len() {
-        0 => Err("No RawSource representation found in IR".into()),
-        1 => Ok(raw_sources[0]),
-        n => Err(format!("Found {} RawSource representations, expected at most 1", n).into()),
-    }
+    Ok(&ir.expect_unique::<RawSource>()?.1.dir)
 }
 
 /// Extract cargo build results from the IR.
 /// Returns the build artifacts or an error if no results or multiple results are found.
 pub fn cargo_build_result(ir: &HarvestIR) -> Result<Vec<PathBuf>, String> {
-    let build_results: Vec<Result<Vec<PathBuf>, String>> = ir
-        .get_by_representation::<CargoBuildResult>()
-        .map(|(_, r)| r.result.clone())
-        .collect();
-
-    match build_results.len() {
-        0 => Err("No artifacts built".into()),
-        1 => build_results[0].clone(),
-        n => Err(format!("Found {} build results, expected at most 1", n)),
+    match ir.expect_unique::<CargoBuildResult>() {
+        Ok((_, r)) => r.result.clone(),
+        Err(NotUnique::Missing(_)) => Err("No artifacts built".into()),
+        Err(NotUnique::Multiple(_, n)) => {
+            Err(format!("Found {} build results, expected at most 1", n))
+        }
     }
 }
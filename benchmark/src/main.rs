@@ -20,7 +20,7 @@ use crate::logger::TeeLogger;
 use crate::stats::{ProgramEvalStats, SummaryStats, TestResult};
 use clap::Parser;
 use harvest_ir::HarvestIR;
-use harvest_translate::{transpile, util::set_user_only_umask};
+use harvest_translate::{transpile, util::set_user_only_umask, TranspileOutcome};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -49,6 +49,19 @@ impl TranspilationResult {
             build_error,
         }
     }
+
+    /// Extract relevant info from a transpile outcome. If a tool failed or the pipeline stalled,
+    /// that is reported as the error, as it is the cause of any missing results in the IR.
+    pub fn from_outcome(outcome: &TranspileOutcome) -> Self {
+        let mut result = Self::from_ir(&outcome.ir);
+        if let Some(run) = outcome.diagnostics.failed_tool_runs().next() {
+            result.build_error = Some(format!("{} {}", run.tool_run_id(), run.result));
+        } else if outcome.is_stalled() {
+            let stalled: Vec<_> = outcome.stalled.iter().map(|t| t.to_string()).collect();
+            result.build_error = Some(format!("Pipeline stalled: {}", stalled.join(", ")));
+        }
+        result
+    }
 }
 
 /// Translates a C source directory to a Rust Cargo project using harvest_translate
@@ -63,6 +76,8 @@ pub fn translate_c_directory_to_rust_project(
         print_config_path: false,
         config: config_overrides.to_vec(),
         force: false,
+        rerun: vec![],
+        resume: None,
     }
     .into();
     let mut config = harvest_translate::cli::initialize(args).expect("Failed to generate config");
@@ -76,14 +91,14 @@ pub fn translate_c_directory_to_rust_project(
         tool_config.model,
         tool_config.max_tokens
     );
-    let ir_result = transpile(config.into());
-    let raw_c_source = raw_source(ir_result.as_ref().unwrap()).unwrap();
+    let outcome = transpile(config.into());
+    let raw_c_source = raw_source(&outcome.as_ref().unwrap().ir).unwrap();
     raw_c_source
         .materialize(output_dir.join("c_src"))
         .expect("Failed to materialize C source");
 
-    match ir_result {
-        Ok(ir) => TranspilationResult::from_ir(&ir),
+    match outcome {
+        Ok(outcome) => TranspilationResult::from_outcome(&outcome),
         Err(_) => TranspilationResult {
             translation_success: false,
             build_success: false,
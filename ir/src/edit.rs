@@ -1,80 +1,380 @@
 //! A system for organizing concurrent mutations to a [HarvestIR].
 
-use crate::{HarvestIR, Id, Representation};
-use std::collections::{HashMap, HashSet};
+use crate::invariant::{IrInvariant, Violation};
+use crate::{HarvestIR, Id, Provenance, Representation, StableKey, TypedId};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem::{replace, take};
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 /// A tool for organizing concurrent modifications to a [HarvestIR]. This owns the `HarvestIR`,
 /// providing read-only access via `snapshot` and an interface to create and apply [Edit]s that
 /// mutate the IR. `Organizer` does not allow two `Edit`s that can modify the same representation
-/// to exist simultaneously.
-#[derive(Default)]
+/// to exist simultaneously, unless the representation is mergeable (see
+/// [Representation::mergeable]), in which case concurrent changes are merged when the edits are
+/// applied.
+///
+/// `Organizer` also keeps every previous version of the IR, which can be retrieved with
+/// `snapshot_at`. `rollback_to` returns the IR to a previous version, abandoning the changes made
+/// since then. The versions share their representations, so keeping the history does not copy any
+/// representation.
 pub struct Organizer {
     ir: Arc<HarvestIR>,
     shared: Arc<Shared>,
+
+    // IR version number. The version starts at 0 and increments by 1 every time an edit is
+    // successfully applied or the IR is rolled back.
+    version: u64,
+
+    // Every version of the IR, indexed by version number. The last entry is the same as `ir`.
+    history: Vec<Arc<HarvestIR>>,
+
+    // The version created by the most recent rollback (or 0 if there has not been a rollback).
+    // Edits created before this version were made from an abandoned IR, and are rejected.
+    rolled_back_at: u64,
+
+    // The IR version in which each ID was last written, removed, or superseded. Used to detect
+    // stale edits.
+    modified: HashMap<Id, u64>,
+
+    // Invariants checked by `check_invariants`.
+    invariants: Vec<Box<dyn IrInvariant>>,
+}
+
+impl Default for Organizer {
+    fn default() -> Organizer {
+        Organizer::with_harvest_ir(HarvestIR::default())
+    }
 }
 
 impl Organizer {
-    /// Constructs a new Organizer with the provided `HarvestIR`.
+    /// Constructs a new Organizer with the provided `HarvestIR`, which becomes version 0.
     pub fn with_harvest_ir(ir: HarvestIR) -> Organizer {
+        let ir = Arc::new(ir);
         Organizer {
-            ir: ir.into(),
+            ir: ir.clone(),
             shared: Default::default(),
+            version: 0,
+            history: vec![ir],
+            rolled_back_at: 0,
+            modified: HashMap::new(),
+            invariants: vec![],
         }
     }
 
-    /// Applies the edit in `Edit` to the IR. This will update the IR and mark the edit's IDs as
-    /// unused.
-    pub fn apply_edit(&mut self, mut edit: Edit) -> Result<(), WrongOrganizer> {
+    /// Applies the edit in `Edit` to the IR. This will update the IR, increment the IR version,
+    /// record the [Provenance] (and, if the edit has a stable producer, the [StableKey]) of every
+    /// representation the edit wrote, and mark the edit's IDs as unused.
+    ///
+    /// The edit is applied atomically: if an error is returned, the IR is not modified. If any
+    /// representation the edit might have read (either declared in `new_edit`'s `might_read` or
+    /// recorded with [Edit::record_read]) was modified after the edit was created, the edit is
+    /// rejected with [ApplyEditError::StaleEdit]. If the IR was rolled back after the edit was
+    /// created, the edit is rejected with [ApplyEditError::RolledBack]. Edits that would leave a
+    /// dangling reference (see [Representation::references]) are rejected with
+    /// [ApplyEditError::DanglingReference].
+    ///
+    /// If the edit writes a mergeable representation that another edit wrote after this edit was
+    /// created, the two changes are combined with [Representation::merge].
+    pub fn apply_edit(&mut self, mut edit: Edit) -> Result<(), ApplyEditError> {
         // Note: we just drop `edit` to mark the IDs as no longer in use.
         if !Arc::ptr_eq(&self.shared, &edit.shared) {
-            return Err(WrongOrganizer);
+            return Err(ApplyEditError::WrongOrganizer);
         }
+        if edit.base_version < self.rolled_back_at {
+            return Err(ApplyEditError::RolledBack);
+        }
+        let modified_since = |id| {
+            self.modified
+                .get(&id)
+                .is_some_and(|&v| v > edit.base_version)
+        };
+        // Concurrent writes to mergeable representations are merged below, so they do not make
+        // the edit stale.
+        let merged = |id| {
+            edit.merging.contains(&id) && matches!(edit.writable.get(&id), Some(Change::Write(_)))
+        };
+        for &id in edit.might_read.iter().chain(&edit.reads) {
+            if modified_since(id) && !merged(id) {
+                return Err(ApplyEditError::StaleEdit(id));
+            }
+        }
+        for &id in &edit.merging {
+            if !modified_since(id) {
+                continue;
+            }
+            let change = edit.writable.get_mut(&id).expect("merging ID not writable");
+            let Change::Write(new) = change else {
+                return Err(ApplyEditError::StaleEdit(id));
+            };
+            let base = &self.history[edit.base_version as usize].representations[&id];
+            let merged = self
+                .ir
+                .representations
+                .get(&id)
+                .and_then(|current| new.merge(&**base, &**current))
+                .ok_or(ApplyEditError::MergeConflict(id))?;
+            *change = Change::Write(merged);
+        }
+        // Returns true if `id` will contain a live representation once `edit` is applied.
+        let live_after = |id| match edit.writable.get(&id) {
+            Some(Change::Write(_)) => true,
+            Some(Change::Remove) => false,
+            Some(Change::Keep) | None => self.ir.contains_id(id),
+        };
+        for (&old, &new) in &edit.supersedes {
+            if old == new || !live_after(old) || !live_after(new) {
+                return Err(ApplyEditError::InvalidSupersede { old, new });
+            }
+        }
+        self.check_references(&edit, live_after)?;
+        let keys = self.stable_keys(&edit);
+        self.version += 1;
+        let provenance = Arc::new(Provenance {
+            producer: edit.producer.take(),
+            version: self.version,
+            inputs: take(&mut edit.reads),
+        });
         let ir = Arc::make_mut(&mut self.ir);
-        for (&id, ref mut representation) in &mut edit.writable {
-            if let Some(representation) = representation.take() {
-                ir.representations.insert(id, representation.into());
+        for (&id, change) in &mut edit.writable {
+            match replace(change, Change::Keep) {
+                Change::Keep => continue,
+                Change::Write(representation) => {
+                    ir.insert(id, representation.into());
+                    ir.provenance.insert(id, provenance.clone());
+                    match keys.get(&id) {
+                        Some(&key) => ir.keys.insert(id, key),
+                        None => ir.keys.remove(&id),
+                    };
+                }
+                Change::Remove => {
+                    ir.remove(id);
+                    ir.provenance.remove(&id);
+                    ir.keys.remove(&id);
+                }
+            }
+            self.modified.insert(id, self.version);
+        }
+        for (&old, &new) in &edit.supersedes {
+            ir.supersede(old, new);
+            self.modified.insert(old, self.version);
+        }
+        self.history.push(self.ir.clone());
+        Ok(())
+    }
+
+    /// Returns an error if applying `edit` would leave a dangling reference (see
+    /// [Representation::references]). `live_after` returns whether an ID will contain a
+    /// representation (ignoring supersedes) once `edit` is applied.
+    fn check_references(
+        &self,
+        edit: &Edit,
+        live_after: impl Fn(Id) -> bool,
+    ) -> Result<(), ApplyEditError> {
+        let live_after = |id| live_after(id) && !edit.supersedes.contains_key(&id);
+        // References from the representations the edit writes.
+        for (&from, change) in &edit.writable {
+            if let Change::Write(representation) = change
+                && live_after(from)
+                && let Some(to) = representation
+                    .references()
+                    .into_iter()
+                    .find(|&to| !live_after(to))
+            {
+                return Err(ApplyEditError::DanglingReference { from, to });
+            }
+        }
+        // References from unmodified representations to the representations the edit removes.
+        let gone = edit
+            .writable
+            .iter()
+            .filter(|(_, change)| matches!(change, Change::Remove))
+            .map(|(&id, _)| id)
+            .chain(edit.supersedes.keys().copied());
+        for to in gone {
+            let rewritten = |id| matches!(edit.writable.get(&id), Some(Change::Write(_)));
+            if let Some(from) = self
+                .ir
+                .referenced_by(to)
+                .find(|&from| live_after(from) && !rewritten(from))
+            {
+                return Err(ApplyEditError::DanglingReference { from, to });
             }
         }
         Ok(())
     }
 
-    /// Creates a new `Edit` that can edit the given list of IDs. All IDs in `might_write` must be
-    /// part of the current IR.
+    /// Derives the stable keys of the representations `edit` writes. The `n`th representation
+    /// (in ID order) with a given name gets the key derived from the edit's stable producer, the
+    /// keys of the representations it read, the name, and `n`. Returns no keys if the edit has no
+    /// stable producer or read a representation that has no key, as the keys would not be stable.
+    fn stable_keys(&self, edit: &Edit) -> HashMap<Id, StableKey> {
+        let Some(producer) = &edit.stable_producer else {
+            return HashMap::new();
+        };
+        let Some(inputs) = edit
+            .reads
+            .iter()
+            .map(|&id| self.ir.stable_key(id))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return HashMap::new();
+        };
+        let mut written =
+            Vec::from_iter(
+                edit.writable
+                    .iter()
+                    .filter_map(|(&id, change)| match change {
+                        Change::Write(representation) => Some((id, representation.name())),
+                        _ => None,
+                    }),
+            );
+        written.sort_unstable_by_key(|&(id, _)| id);
+        let mut counts = HashMap::<&str, u64>::new();
+        written
+            .into_iter()
+            .map(|(id, name)| {
+                let index = counts.entry(name).or_default();
+                let key = StableKey::derive(producer, inputs.iter().copied(), name, *index);
+                *index += 1;
+                (id, key)
+            })
+            .collect()
+    }
+
+    /// Creates a new `Edit` that can edit the given list of IDs. All IDs in `might_write` and
+    /// `might_read` must be part of the current IR.
     ///
     /// The IDs in `might_write` will be marked as in use, and will only be freed when either the
-    /// edit is applied (via [Organizer::apply_edit]) or dropped.
-    pub fn new_edit(&mut self, might_write: &HashSet<Id>) -> Result<Edit, NewEditError> {
+    /// edit is applied (via [Organizer::apply_edit]) or dropped. The IDs in `might_read` are not
+    /// reserved, but if any of them is modified before the edit is applied then the edit will be
+    /// rejected as stale.
+    pub fn new_edit(
+        &mut self,
+        might_write: &HashSet<Id>,
+        might_read: &HashSet<Id>,
+    ) -> Result<Edit, NewEditError> {
         // An unknown ID generally represents a bug in the calling code, whereas IdInUse can be a
         // normal situation. Therefore, prioritize returning UnknownId so that IdInUse doesn't hide
         // bugs.
-        if might_write.iter().any(|&id| !self.ir.contains_id(id)) {
+        if might_write
+            .iter()
+            .chain(might_read)
+            .any(|&id| !self.ir.contains_id(id))
+        {
             return Err(NewEditError::UnknownId);
         }
+        let mergeable = |id| self.ir.representations[&id].mergeable();
+        // Lock order: in_use, then merging.
         let mut in_use = self.shared.in_use.lock().expect("in_use poisoned");
-        if might_write.iter().any(|id| in_use.contains(id)) {
+        let mut merging = self.shared.merging.lock().expect("merging poisoned");
+        if might_write
+            .iter()
+            .any(|id| in_use.contains(id) || (merging.contains_key(id) && !mergeable(*id)))
+        {
             return Err(NewEditError::IdInUse);
         }
-        might_write.iter().for_each(|&id| {
-            in_use.insert(id);
-        });
+        let (shared, exclusive): (HashSet<Id>, _) =
+            might_write.iter().partition(|&&id| mergeable(id));
+        shared
+            .iter()
+            .for_each(|&id| *merging.entry(id).or_default() += 1);
+        in_use.extend(exclusive);
         Ok(Edit {
             shared: self.shared.clone(),
-            writable: might_write.iter().map(|&id| (id, None)).collect(),
+            writable: might_write.iter().map(|&id| (id, Change::Keep)).collect(),
+            supersedes: HashMap::new(),
+            producer: None,
+            stable_producer: None,
+            reads: BTreeSet::new(),
+            base_version: self.version,
+            might_read: might_read.clone(),
+            merging: shared,
         })
     }
 
+    /// Adds an invariant to be checked by `check_invariants`.
+    pub fn add_invariant(&mut self, invariant: Box<dyn IrInvariant>) {
+        self.invariants.push(invariant);
+    }
+
+    /// Checks the current IR against every invariant added with `add_invariant`, returning the
+    /// invariants it violates.
+    pub fn check_invariants(&self) -> Vec<Violation> {
+        self.invariants
+            .iter()
+            .filter_map(|invariant| {
+                let message = invariant.check(&self.ir).err()?;
+                Some(Violation {
+                    invariant: invariant.name(),
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the current IR version: the number of edits that have been applied (plus the
+    /// number of rollbacks that have been performed) by this `Organizer`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Returns the current value of the IR.
     pub fn snapshot(&self) -> Arc<HarvestIR> {
         self.ir.clone()
     }
+
+    /// Returns the value the IR had at version `version`, or `None` if that version does not exist
+    /// yet.
+    pub fn snapshot_at(&self, version: u64) -> Option<Arc<HarvestIR>> {
+        self.history.get(usize::try_from(version).ok()?).cloned()
+    }
+
+    /// Returns the IR to the value it had at version `version`, abandoning all changes made since.
+    /// The rollback itself creates a new IR version (so the abandoned versions remain available
+    /// via `snapshot_at`), which is returned.
+    ///
+    /// Edits that were created before the rollback can no longer be applied, as they were computed
+    /// from the abandoned IR.
+    pub fn rollback_to(&mut self, version: u64) -> Result<u64, UnknownVersion> {
+        let ir = self.snapshot_at(version).ok_or(UnknownVersion(version))?;
+        self.version += 1;
+        self.ir = ir;
+        self.history.push(self.ir.clone());
+        self.rolled_back_at = self.version;
+        Ok(self.version)
+    }
 }
 
 /// Error type returned by `Organizer::apply_edit`.
 #[derive(Debug, Error, Hash, PartialEq)]
-#[error("edit is for a different Organizer")]
-pub struct WrongOrganizer;
+pub enum ApplyEditError {
+    /// The edit would leave the representation `from` referring to `to`, which would not exist
+    /// (or would be superseded).
+    #[error("{from} would refer to {to}, which does not exist")]
+    DanglingReference { from: Id, to: Id },
+    #[error("{old} cannot be superseded by {new}")]
+    InvalidSupersede { old: Id, new: Id },
+    /// Another edit changed the mergeable representation with this ID after this edit was
+    /// created, and [Representation::merge] could not combine the changes.
+    #[error("conflicting changes to {0} could not be merged")]
+    MergeConflict(Id),
+    /// A representation the edit might have read was modified after the edit was created, so the
+    /// edit may have been computed from outdated data. The edit should be recomputed from a newer
+    /// snapshot.
+    #[error("{0} was modified after this edit was created")]
+    StaleEdit(Id),
+    #[error("the IR was rolled back after this edit was created")]
+    RolledBack,
+    #[error("edit is for a different Organizer")]
+    WrongOrganizer,
+}
+
+/// Error type returned by `Organizer::rollback_to`.
+#[derive(Debug, Error, Hash, PartialEq)]
+#[error("IR version {0} does not exist")]
+pub struct UnknownVersion(pub u64);
 
 /// Error type returned by `Organizer::new_edit`.
 #[derive(Debug, Error, Hash, PartialEq)]
@@ -96,29 +396,123 @@ pub enum NewEditError {
 /// 2. Clone the Representation to get an owned copy.
 /// 3. Edit the copied Representation.
 /// 4. Store the edited Representation into `context.ir_edit` using `write_id`.
+///
+/// To replace a representation with a new one under a different ID (e.g. an improved version of
+/// the same program), add the new representation and then call `supersede`. Superseded
+/// representations are hidden from normal IR lookups, but remain in the IR's history.
 pub struct Edit {
     shared: Arc<Shared>,
 
-    // Contains every ID this tool can write. IDs that contain Some() will be
-    // written, and IDs that contain None will not be touched.
-    writable: HashMap<Id, Option<Box<dyn Representation>>>,
+    // Contains every ID this tool can write, and the change (if any) to make to that ID.
+    writable: HashMap<Id, Change>,
+
+    // Representations this edit will mark as superseded, mapped to the ID that supersedes them.
+    supersedes: HashMap<Id, Id>,
+
+    // Provenance information for the representations written by this edit.
+    producer: Option<String>,
+    stable_producer: Option<String>,
+    reads: BTreeSet<Id>,
+
+    // The IR version this edit was created at, and the IDs it declared it might read. Used to
+    // detect stale edits.
+    base_version: u64,
+    might_read: HashSet<Id>,
+
+    // The mergeable IDs this edit can write, which other edits may write concurrently.
+    merging: HashSet<Id>,
+}
+
+/// A change to a single ID in an [Edit].
+enum Change {
+    /// Leave the ID as-is.
+    Keep,
+    /// Remove the representation with this ID.
+    Remove,
+    /// Write a new representation to this ID.
+    Write(Box<dyn Representation>),
 }
 
 impl Edit {
     /// Adds a representation with a new ID and returns the new ID.
     pub fn add_representation(&mut self, representation: Box<dyn Representation>) -> Id {
         let id = Id::new();
-        self.writable.insert(id, Some(representation));
+        self.writable.insert(id, Change::Write(representation));
         id
     }
 
+    /// Adds a representation with a new ID and returns the new ID as a [TypedId].
+    pub fn add_typed<R: Representation>(&mut self, representation: R) -> TypedId<R> {
+        TypedId::new_unchecked(self.add_representation(Box::new(representation)))
+    }
+
     /// Creates a new ID and gives this tool write access to it.
     pub fn new_id(&mut self) -> Id {
         let id = Id::new();
-        self.writable.insert(id, None);
+        self.writable.insert(id, Change::Keep);
         id
     }
 
+    /// Removes the representation with the given `id` from the IR. Errors if this tool cannot
+    /// write `id`.
+    pub fn try_remove_id(&mut self, id: Id) -> Result<(), NotWritable> {
+        self.writable
+            .get_mut(&id)
+            .map(|v| *v = Change::Remove)
+            .ok_or(NotWritable)
+    }
+
+    /// Removes the representation with the given `id` from the IR. Panics if this tool cannot
+    /// write `id`.
+    #[track_caller]
+    pub fn remove_id(&mut self, id: Id) {
+        if self.try_remove_id(id).is_err() {
+            panic!("cannot write this id");
+        }
+    }
+
+    /// Marks the representation `old` as superseded by the representation `new`. Errors if this
+    /// tool cannot write `old`.
+    ///
+    /// When this edit is applied, both `old` and `new` must refer to representations, or
+    /// [Organizer::apply_edit] will fail.
+    pub fn try_supersede(&mut self, old: Id, new: Id) -> Result<(), NotWritable> {
+        if !self.writable.contains_key(&old) {
+            return Err(NotWritable);
+        }
+        self.supersedes.insert(old, new);
+        Ok(())
+    }
+
+    /// Marks the representation `old` as superseded by the representation `new`. Panics if this
+    /// tool cannot write `old`.
+    #[track_caller]
+    pub fn supersede(&mut self, old: Id, new: Id) {
+        if self.try_supersede(old, new).is_err() {
+            panic!("cannot write this id");
+        }
+    }
+
+    /// Sets the name of the producer (e.g. a tool run) that made this edit. This is recorded in the
+    /// [Provenance] of every representation the edit writes.
+    pub fn set_producer(&mut self, producer: String) {
+        self.producer = Some(producer);
+    }
+
+    /// Sets a run-independent name for the producer of this edit (for tools, the tool's name, as
+    /// opposed to the tool run ID passed to `set_producer`). If this is set, every representation
+    /// the edit writes is given a [StableKey] derived from this name and the keys of the
+    /// representations recorded with `record_read`.
+    pub fn set_stable_producer(&mut self, name: String) {
+        self.stable_producer = Some(name);
+    }
+
+    /// Records that the representations written by this edit were derived from the representation
+    /// `id`. This is recorded in the [Provenance] of every representation the edit writes.
+    pub fn record_read(&mut self, id: Id) {
+        self.reads.insert(id);
+    }
+
     /// Writes `representation` to the given `id`. Errors if this tool cannot
     /// write `id`.
     pub fn try_write_id(
@@ -128,7 +522,7 @@ impl Edit {
     ) -> Result<(), NotWritable> {
         self.writable
             .get_mut(&id)
-            .map(|v| *v = Some(representation))
+            .map(|v| *v = Change::Write(representation))
             .ok_or(NotWritable)
     }
 
@@ -140,15 +534,42 @@ impl Edit {
             panic!("cannot write this id");
         }
     }
+
+    /// Writes `representation` to the given `id`, which keeps its type. Errors if this tool
+    /// cannot write `id`.
+    pub fn try_write_typed<R: Representation>(
+        &mut self,
+        id: TypedId<R>,
+        representation: R,
+    ) -> Result<(), NotWritable> {
+        self.try_write_id(id.id(), Box::new(representation))
+    }
+
+    /// Writes `representation` to the given `id`, which keeps its type. Panics if this tool
+    /// cannot write `id`.
+    #[track_caller]
+    pub fn write_typed<R: Representation>(&mut self, id: TypedId<R>, representation: R) {
+        if self.try_write_typed(id, representation).is_err() {
+            panic!("cannot write this id");
+        }
+    }
 }
 
 impl Drop for Edit {
     fn drop(&mut self) {
         // Mark this Edit's IDs as no longer in use.
         let mut in_use = self.shared.in_use.lock().expect("in_use poisoned");
-        self.writable.iter().for_each(|(id, _)| {
-            in_use.remove(id);
-        });
+        let mut merging = self.shared.merging.lock().expect("merging poisoned");
+        for id in self.writable.keys() {
+            if !self.merging.contains(id) {
+                in_use.remove(id);
+            } else if let Entry::Occupied(mut count) = merging.entry(*id) {
+                *count.get_mut() -= 1;
+                if *count.get() == 0 {
+                    count.remove();
+                }
+            }
+        }
     }
 }
 
@@ -160,13 +581,18 @@ pub struct NotWritable;
 /// State shared between the `Organizer` and the `Edit`s it creates.
 #[derive(Default)]
 struct Shared {
+    // IDs written by an edit exclusively.
     in_use: Mutex<HashSet<Id>>,
+    // Mergeable IDs, and the number of edits that can write them.
+    merging: Mutex<HashMap<Id, usize>>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests::EmptyRepresentation;
+    use crate::tests::{
+        EmptyRepresentation, IdRepresentation, ListRepresentation, RefRepresentation,
+    };
 
     #[test]
     fn organizer() {
@@ -174,7 +600,7 @@ mod tests {
 
         // Apply an edit to add two new reprs to the IR.
         let mut edit = organizer
-            .new_edit(&[].into())
+            .new_edit(&[].into(), &[].into())
             .expect("no-ID new_edit failed");
         let [a, b, c] = [
             edit.add_representation(Box::new(EmptyRepresentation)),
@@ -190,8 +616,12 @@ mod tests {
         );
 
         // Nested change creation: create two Edits. Apply the second one, then drop the first.
-        let mut edit1 = organizer.new_edit(&[a].into()).expect("new_edit failed");
-        let mut edit2 = organizer.new_edit(&[b].into()).expect("new_edit failed");
+        let mut edit1 = organizer
+            .new_edit(&[a].into(), &[].into())
+            .expect("new_edit failed");
+        let mut edit2 = organizer
+            .new_edit(&[b].into(), &[].into())
+            .expect("new_edit failed");
         assert_eq!(
             *organizer.shared.in_use.lock().expect("in_use poisoned"),
             HashSet::from([a, b])
@@ -218,22 +648,24 @@ mod tests {
         assert_eq!(
             organizer.apply_edit(
                 Organizer::default()
-                    .new_edit(&[].into())
+                    .new_edit(&[].into(), &[].into())
                     .expect("new_edit failed")
             ),
-            Err(WrongOrganizer),
+            Err(ApplyEditError::WrongOrganizer),
             "apply_edit accepted Edit from another Organizer"
         );
 
         // new_edit error cases.
-        let edit = organizer.new_edit(&[f].into()).expect("new_edit failed");
+        let edit = organizer
+            .new_edit(&[f].into(), &[].into())
+            .expect("new_edit failed");
         assert_eq!(
-            organizer.new_edit(&[c, f].into()).err(),
+            organizer.new_edit(&[c, f].into(), &[].into()).err(),
             Some(NewEditError::UnknownId),
             "with both an unknown ID and an in use ID, new_edit should return an UnknownId error"
         );
         assert_eq!(
-            organizer.new_edit(&[f, g].into()).err(),
+            organizer.new_edit(&[f, g].into(), &[].into()).err(),
             Some(NewEditError::IdInUse),
             "new_edit accepted in use ID"
         );
@@ -243,12 +675,12 @@ mod tests {
     #[test]
     fn edit() {
         let [a, b, c] = Id::new_array();
-        let mut organizer = Organizer::with_harvest_ir(HarvestIR {
-            representations: [a, b]
-                .map(|id| (id, Arc::new(EmptyRepresentation) as Arc<_>))
-                .into(),
-        });
-        let mut edit = organizer.new_edit(&[a, b].into()).unwrap();
+        let mut ir = HarvestIR::default();
+        for id in [a, b] {
+            ir.insert(id, Arc::new(EmptyRepresentation));
+        }
+        let mut organizer = Organizer::with_harvest_ir(ir);
+        let mut edit = organizer.new_edit(&[a, b].into(), &[].into()).unwrap();
         let d = edit.add_representation(Box::new(EmptyRepresentation));
         let e = edit.new_id();
         assert_eq!(
@@ -267,11 +699,377 @@ mod tests {
             HashSet::from_iter(
                 edit.writable
                     .iter()
-                    .filter(|(_, r)| r.is_some())
+                    .filter(|(_, c)| matches!(c, Change::Write(_)))
                     .map(|(&i, _)| i)
             ),
             HashSet::from([a, d, e]),
             "changed IDs incorrect"
         );
     }
+
+    #[test]
+    fn remove_and_supersede() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let [a, b, c] = [1, 2, 3].map(|i| edit.add_representation(Box::new(IdRepresentation(i))));
+        organizer.apply_edit(edit).unwrap();
+
+        let mut edit = organizer.new_edit(&[a, b].into(), &[].into()).unwrap();
+        edit.remove_id(a);
+        let d = edit.add_representation(Box::new(IdRepresentation(4)));
+        edit.supersede(b, d);
+        assert_eq!(edit.try_remove_id(c), Err(NotWritable));
+        assert_eq!(edit.try_supersede(c, d), Err(NotWritable));
+        organizer.apply_edit(edit).unwrap();
+        let ir = organizer.snapshot();
+        assert_eq!(
+            Vec::from_iter(ir.get_by_representation::<IdRepresentation>()),
+            [(c, &IdRepresentation(3)), (d, &IdRepresentation(4))]
+        );
+        assert!(!ir.contains_id(a) && !ir.contains_id(b));
+        assert_eq!(ir.superseded_by(b), Some(d));
+        assert_eq!(ir.superseded_by(a), None);
+        assert_eq!(
+            Vec::from_iter(ir.superseded().map(|(id, r)| (id, r.to_string()))),
+            [(b, "IdRepresentation(2)".into())]
+        );
+
+        // Invalid supersede relations should be rejected without modifying the IR.
+        let mut edit = organizer.new_edit(&[c, d].into(), &[].into()).unwrap();
+        edit.remove_id(d);
+        edit.supersede(c, d);
+        assert_eq!(
+            organizer.apply_edit(edit),
+            Err(ApplyEditError::InvalidSupersede { old: c, new: d })
+        );
+        let mut edit = organizer.new_edit(&[c].into(), &[].into()).unwrap();
+        edit.supersede(c, c);
+        assert_eq!(
+            organizer.apply_edit(edit),
+            Err(ApplyEditError::InvalidSupersede { old: c, new: c })
+        );
+        assert!(Arc::ptr_eq(&ir, &organizer.snapshot()));
+    }
+
+    #[test]
+    fn provenance() {
+        let mut organizer = Organizer::default();
+        assert_eq!(organizer.version(), 0);
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_representation(Box::new(IdRepresentation(1)));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.version(), 1);
+        let ir = organizer.snapshot();
+        assert_eq!(
+            ir.provenance(a),
+            Some(&Provenance {
+                producer: None,
+                version: 1,
+                inputs: [].into()
+            })
+        );
+
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.set_producer("tool_001".into());
+        edit.record_read(a);
+        let b = edit.add_representation(Box::new(IdRepresentation(2)));
+        let c = edit.new_id();
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.version(), 2);
+        let ir = organizer.snapshot();
+        assert_eq!(ir.provenance(a).map(|p| p.version), Some(1));
+        assert_eq!(
+            ir.provenance(b),
+            Some(&Provenance {
+                producer: Some("tool_001".into()),
+                version: 2,
+                inputs: [a].into()
+            })
+        );
+        assert_eq!(ir.provenance(c), None);
+
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.remove_id(a);
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.snapshot().provenance(a), None);
+    }
+
+    #[test]
+    fn stale_edit() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let [a, b] = [1, 2].map(|i| edit.add_representation(Box::new(IdRepresentation(i))));
+        organizer.apply_edit(edit).unwrap();
+
+        let reader = organizer.new_edit(&[].into(), &[a].into()).unwrap();
+        let mut recorder = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        recorder.record_read(a);
+        let mut unrelated = organizer.new_edit(&[].into(), &[b].into()).unwrap();
+        unrelated.add_representation(Box::new(IdRepresentation(3)));
+        let mut writer = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        writer.write_id(a, Box::new(IdRepresentation(4)));
+        organizer.apply_edit(writer).unwrap();
+        assert_eq!(
+            organizer.apply_edit(reader),
+            Err(ApplyEditError::StaleEdit(a))
+        );
+        assert_eq!(
+            organizer.apply_edit(recorder),
+            Err(ApplyEditError::StaleEdit(a))
+        );
+        organizer.apply_edit(unrelated).unwrap();
+        // Edits created after the modification are not stale.
+        let reader = organizer.new_edit(&[].into(), &[a].into()).unwrap();
+        organizer.apply_edit(reader).unwrap();
+        assert_eq!(
+            organizer.new_edit(&[].into(), &[Id::new()].into()).err(),
+            Some(NewEditError::UnknownId)
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_representation(Box::new(IdRepresentation(1)));
+        organizer.apply_edit(edit).unwrap();
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.write_id(a, Box::new(IdRepresentation(2)));
+        let b = edit.add_representation(Box::new(IdRepresentation(3)));
+        organizer.apply_edit(edit).unwrap();
+        let ids = |ir: Arc<HarvestIR>| {
+            Vec::from_iter(
+                ir.get_by_representation()
+                    .map(|(id, &IdRepresentation(i))| (id, i)),
+            )
+        };
+        assert_eq!(ids(organizer.snapshot_at(0).unwrap()), []);
+        assert_eq!(ids(organizer.snapshot_at(1).unwrap()), [(a, 1)]);
+        assert_eq!(ids(organizer.snapshot_at(2).unwrap()), [(a, 2), (b, 3)]);
+        assert!(organizer.snapshot_at(3).is_none());
+
+        // Roll back, abandoning an in-progress edit.
+        let mut abandoned = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        abandoned.add_representation(Box::new(IdRepresentation(4)));
+        assert_eq!(organizer.rollback_to(4), Err(UnknownVersion(4)));
+        assert_eq!(organizer.rollback_to(1), Ok(3));
+        assert_eq!(organizer.version(), 3);
+        assert!(Arc::ptr_eq(
+            &organizer.snapshot(),
+            &organizer.snapshot_at(1).unwrap()
+        ));
+        assert_eq!(
+            organizer.apply_edit(abandoned),
+            Err(ApplyEditError::RolledBack)
+        );
+        assert_eq!(
+            organizer.new_edit(&[b].into(), &[].into()).err(),
+            Some(NewEditError::UnknownId)
+        );
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.write_id(a, Box::new(IdRepresentation(5)));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(ids(organizer.snapshot_at(2).unwrap()), [(a, 2), (b, 3)]);
+        assert_eq!(ids(organizer.snapshot()), [(a, 5)]);
+        assert_eq!(organizer.version(), 4);
+    }
+
+    #[test]
+    fn invariants() {
+        use crate::invariant::AtMostOne;
+        let mut organizer = Organizer::default();
+        organizer.add_invariant(Box::new(AtMostOne::<IdRepresentation>::new()));
+        organizer.add_invariant(Box::new(AtMostOne::<EmptyRepresentation>::new()));
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        edit.add_representation(Box::new(IdRepresentation(1)));
+        edit.add_representation(Box::new(EmptyRepresentation));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.check_invariants(), []);
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        edit.add_representation(Box::new(IdRepresentation(2)));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(
+            organizer.check_invariants(),
+            [Violation {
+                invariant: "at_most_one_IdRepresentation".into(),
+                message: "found 2 IdRepresentation representations".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn typed() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_typed(IdRepresentation(1));
+        let b = edit.add_representation(Box::new(EmptyRepresentation));
+        organizer.apply_edit(edit).unwrap();
+        let ir = organizer.snapshot();
+        assert_eq!(ir.get(a), Some(&IdRepresentation(1)));
+        assert_eq!(ir.typed_id::<IdRepresentation>(a.id()), Some(a));
+        assert_eq!(ir.typed_id::<IdRepresentation>(b), None);
+        assert_eq!(ir.typed_id::<IdRepresentation>(Id::new()), None);
+
+        let mut edit = organizer.new_edit(&[a.id()].into(), &[].into()).unwrap();
+        edit.write_typed(a, IdRepresentation(2));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.snapshot().get(a), Some(&IdRepresentation(2)));
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        assert_eq!(
+            edit.try_write_typed(a, IdRepresentation(3)),
+            Err(NotWritable)
+        );
+
+        // Replacing the representation with another type through an untyped ID is detected.
+        let mut edit = organizer.new_edit(&[a.id()].into(), &[].into()).unwrap();
+        edit.write_id(a.id(), Box::new(EmptyRepresentation));
+        organizer.apply_edit(edit).unwrap();
+        assert_eq!(organizer.snapshot().get(a), None);
+    }
+
+    #[test]
+    fn dangling_references() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_representation(Box::new(EmptyRepresentation));
+        edit.add_representation(Box::new(RefRepresentation(vec![a])));
+        let unknown = Id::new();
+        let c = edit.add_representation(Box::new(RefRepresentation(vec![unknown])));
+        assert_eq!(
+            organizer.apply_edit(edit),
+            Err(ApplyEditError::DanglingReference {
+                from: c,
+                to: unknown
+            })
+        );
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_representation(Box::new(EmptyRepresentation));
+        let b = edit.add_representation(Box::new(RefRepresentation(vec![a])));
+        assert_eq!(organizer.apply_edit(edit), Ok(()));
+
+        // Removing or superseding a referenced representation is rejected...
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.remove_id(a);
+        let expected = Err(ApplyEditError::DanglingReference { from: b, to: a });
+        assert_eq!(organizer.apply_edit(edit), expected);
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        let new_a = edit.add_representation(Box::new(EmptyRepresentation));
+        edit.supersede(a, new_a);
+        assert_eq!(organizer.apply_edit(edit), expected);
+
+        // ...unless the referring representation is updated or removed in the same edit.
+        let mut edit = organizer.new_edit(&[a, b].into(), &[].into()).unwrap();
+        let new_a = edit.add_representation(Box::new(EmptyRepresentation));
+        edit.supersede(a, new_a);
+        edit.write_id(b, Box::new(RefRepresentation(vec![new_a])));
+        assert_eq!(organizer.apply_edit(edit), Ok(()));
+        assert_eq!(
+            Vec::from_iter(organizer.snapshot().referenced_by(new_a)),
+            [b]
+        );
+        let mut edit = organizer.new_edit(&[new_a, b].into(), &[].into()).unwrap();
+        edit.remove_id(new_a);
+        edit.remove_id(b);
+        assert_eq!(organizer.apply_edit(edit), Ok(()));
+    }
+
+    #[test]
+    fn stable_keys() {
+        // Simulates a run: a loader with no inputs, then a tool that reads the loader's output.
+        let run = || {
+            let mut organizer = Organizer::default();
+            let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+            edit.set_stable_producer("loader".into());
+            let a = edit.add_representation(Box::new(IdRepresentation(1)));
+            let b = edit.add_representation(Box::new(IdRepresentation(2)));
+            organizer.apply_edit(edit).unwrap();
+            let mut edit = organizer.new_edit(&[].into(), &[a].into()).unwrap();
+            edit.set_stable_producer("tool".into());
+            edit.record_read(a);
+            let c = edit.add_representation(Box::new(EmptyRepresentation));
+            organizer.apply_edit(edit).unwrap();
+            (organizer, [a, b, c])
+        };
+        let (first, first_ids) = run();
+        // Allocate some IDs so the second run's IDs differ.
+        Id::new_array::<3>();
+        let (second, second_ids) = run();
+        let keys = |organizer: &Organizer, ids: [Id; 3]| {
+            ids.map(|id| organizer.snapshot().stable_key(id).unwrap())
+        };
+        let first_keys = keys(&first, first_ids);
+        assert_ne!(first_ids, second_ids);
+        assert_eq!(first_keys, keys(&second, second_ids));
+        let [a, b, c] = first_keys;
+        assert!(a != b && b != c && a != c);
+        assert_eq!(second.snapshot().find_by_key(c), Some(second_ids[2]));
+
+        // Edits without a stable producer, or that read a representation without a key, do not
+        // assign keys. Rewriting a representation without a key drops its old key.
+        let mut organizer = first;
+        let [a, b, _] = first_ids;
+        let mut edit = organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.write_id(a, Box::new(IdRepresentation(3)));
+        let d = edit.add_representation(Box::new(EmptyRepresentation));
+        organizer.apply_edit(edit).unwrap();
+        let mut edit = organizer.new_edit(&[b].into(), &[d].into()).unwrap();
+        edit.set_stable_producer("tool".into());
+        edit.record_read(d);
+        edit.write_id(b, Box::new(IdRepresentation(4)));
+        organizer.apply_edit(edit).unwrap();
+        let ir = organizer.snapshot();
+        assert_eq!([a, b, d].map(|id| ir.stable_key(id)), [None; 3]);
+        assert_eq!(ir.find_by_key(first_keys[0]), None);
+    }
+
+    #[test]
+    fn merge() {
+        let mut organizer = Organizer::default();
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let list = edit.add_typed(ListRepresentation(vec![1]));
+        let other = edit.add_representation(Box::new(EmptyRepresentation));
+        organizer.apply_edit(edit).unwrap();
+        let l = list.id();
+
+        // Several edits can write a mergeable representation at once, and their appends are
+        // merged.
+        let [mut a, mut b, mut c] =
+            [(); 3].map(|_| organizer.new_edit(&[l].into(), &[l].into()).unwrap());
+        assert_eq!(
+            *organizer.shared.merging.lock().expect("merging poisoned"),
+            HashMap::from([(l, 3)])
+        );
+        a.write_typed(list, ListRepresentation(vec![1, 2]));
+        b.write_typed(list, ListRepresentation(vec![1, 3]));
+        b.record_read(l);
+        c.write_typed(list, ListRepresentation(vec![]));
+        assert_eq!(organizer.apply_edit(a), Ok(()));
+        assert_eq!(organizer.apply_edit(b), Ok(()));
+        let expected = ListRepresentation(vec![1, 2, 3]);
+        assert_eq!(organizer.snapshot().get(list), Some(&expected));
+        // Changes that merge() cannot combine are rejected.
+        assert_eq!(
+            organizer.apply_edit(c),
+            Err(ApplyEditError::MergeConflict(l))
+        );
+        assert!(organizer.shared.merging.lock().unwrap().is_empty());
+
+        // An edit that only reads the representation is still stale.
+        let mut reader = organizer.new_edit(&[other].into(), &[l].into()).unwrap();
+        reader.remove_id(other);
+        let mut writer = organizer.new_edit(&[l].into(), &[].into()).unwrap();
+        writer.write_typed(list, ListRepresentation(vec![1, 2, 3, 4]));
+        organizer.apply_edit(writer).unwrap();
+        assert_eq!(
+            organizer.apply_edit(reader),
+            Err(ApplyEditError::StaleEdit(l))
+        );
+
+        // Non-mergeable representations are still written exclusively.
+        let _edit = organizer.new_edit(&[other].into(), &[].into()).unwrap();
+        assert_eq!(
+            organizer.new_edit(&[other].into(), &[].into()).err(),
+            Some(NewEditError::IdInUse)
+        );
+    }
 }
@@ -0,0 +1,330 @@
+//! Conversion between [RawDir]s and archive files (tarballs and zip files).
+
+use super::{FileMetadata, RawDir, RawEntry};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{EntryType, Header};
+use thiserror::Error;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// File mode used for directories and symlinks in exported archives.
+const DIR_MODE: u32 = 0o755;
+
+/// The archive formats supported by [RawDir::read_archive] and [RawDir::write_archive].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ArchiveFormat {
+    /// An uncompressed tarball (`.tar`).
+    Tar,
+    /// A gzip-compressed tarball (`.tar.gz` or `.tgz`).
+    TarGz,
+    /// A zip file (`.zip`).
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Determines the archive format from a file name's extension, returning `None` if it is not a
+    /// recognized archive extension.
+    pub fn from_path(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        match () {
+            _ if name.ends_with(".tar") => Some(ArchiveFormat::Tar),
+            _ if name.ends_with(".tar.gz") || name.ends_with(".tgz") => Some(ArchiveFormat::TarGz),
+            _ if name.ends_with(".zip") => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+impl RawDir {
+    /// Reads the archive at `path`, whose format is determined by its extension (see
+    /// [ArchiveFormat::from_path]).
+    pub fn read_archive<P: AsRef<Path>>(path: P) -> Result<RawDir, ArchiveError> {
+        let path = path.as_ref();
+        let format = ArchiveFormat::from_path(path)
+            .ok_or_else(|| ArchiveError::UnknownFormat(path.into()))?;
+        let file = BufReader::new(File::open(path)?);
+        match format {
+            ArchiveFormat::Tar => RawDir::from_tar(file),
+            ArchiveFormat::TarGz => RawDir::from_tar(GzDecoder::new(file)),
+            ArchiveFormat::Zip => RawDir::from_zip(file),
+        }
+    }
+
+    /// Writes this directory to a new archive at `path`, whose format is determined by its
+    /// extension (see [ArchiveFormat::from_path]).
+    pub fn write_archive<P: AsRef<Path>>(&self, path: P) -> Result<(), ArchiveError> {
+        let path = path.as_ref();
+        let format = ArchiveFormat::from_path(path)
+            .ok_or_else(|| ArchiveError::UnknownFormat(path.into()))?;
+        let file = BufWriter::new(File::create_new(path)?);
+        let file = match format {
+            ArchiveFormat::Tar => self.to_tar(file)?,
+            ArchiveFormat::TarGz => self
+                .to_tar(GzEncoder::new(file, Compression::default()))?
+                .finish()?,
+            ArchiveFormat::Zip => self.to_zip(file)?,
+        };
+        file.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    }
+
+    /// Reads a tarball (which must not be compressed). Regular files, directories, and symlinks
+    /// are supported; hard links and special files result in an error.
+    ///
+    /// Paths in the archive must be relative and stay within the archive (`..` is not allowed).
+    /// File modes are normalized as described in [FileMetadata::mode]; timestamps are discarded.
+    pub fn from_tar<R: Read>(reader: R) -> Result<RawDir, ArchiveError> {
+        let mut dir = RawDir::default();
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let new_entry = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => {
+                    let metadata = FileMetadata::normalized(entry.header().mode()?);
+                    let mut contents = vec![];
+                    entry.read_to_end(&mut contents)?;
+                    RawEntry::File(contents.into(), metadata)
+                }
+                EntryType::Directory => RawEntry::Dir(RawDir::default()),
+                EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => RawEntry::Symlink(target.into_owned()),
+                    None => return Err(ArchiveError::Unsupported(path)),
+                },
+                // Extended headers are handled by the tar crate; global ones carry no entries.
+                EntryType::XGlobalHeader => continue,
+                _ => return Err(ArchiveError::Unsupported(path)),
+            };
+            dir.insert_archive_entry(&path, new_entry)?;
+        }
+        Ok(dir)
+    }
+
+    /// Writes this directory as an (uncompressed) tarball to `writer`, returning `writer`. Files
+    /// keep the mode in their [FileMetadata], and directories are given mode 755.
+    pub fn to_tar<W: Write>(&self, writer: W) -> io::Result<W> {
+        let mut builder = tar::Builder::new(writer);
+        self.visit_all(&mut PathBuf::new(), &mut |path, entry| {
+            let mut header = Header::new_gnu();
+            header.set_mtime(0);
+            match entry {
+                RawEntry::Dir(_) => {
+                    header.set_entry_type(EntryType::Directory);
+                    header.set_mode(DIR_MODE);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, io::empty())
+                }
+                RawEntry::File(contents, metadata) => {
+                    header.set_entry_type(EntryType::Regular);
+                    let contents = contents.try_get()?;
+                    header.set_mode(metadata.mode());
+                    header.set_size(contents.len() as u64);
+                    builder.append_data(&mut header, path, contents)
+                }
+                RawEntry::Symlink(target) => {
+                    header.set_entry_type(EntryType::Symlink);
+                    header.set_mode(DIR_MODE);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, target)
+                }
+            }
+        })?;
+        builder.into_inner()
+    }
+
+    /// Reads a zip file. Paths in the archive must be relative and stay within the archive (`..`
+    /// is not allowed). File modes are normalized as described in [FileMetadata::mode];
+    /// timestamps are discarded.
+    pub fn from_zip<R: Read + Seek>(reader: R) -> Result<RawDir, ArchiveError> {
+        let mut archive = ZipArchive::new(reader)?;
+        let mut dir = RawDir::default();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let path = PathBuf::from(&*file.name()?);
+            let mut contents = vec![];
+            let entry = match (file.is_dir(), file.is_symlink()) {
+                (true, _) => RawEntry::Dir(RawDir::default()),
+                (false, true) => {
+                    file.read_to_end(&mut contents)?;
+                    let target = String::from_utf8(contents)
+                        .map_err(|_| ArchiveError::Unsupported(path.clone()))?;
+                    RawEntry::Symlink(target.into())
+                }
+                (false, false) => {
+                    let metadata = file.unix_mode().map(FileMetadata::normalized);
+                    file.read_to_end(&mut contents)?;
+                    RawEntry::File(contents.into(), metadata.unwrap_or_default())
+                }
+            };
+            drop(file);
+            dir.insert_archive_entry(&path, entry)?;
+        }
+        Ok(dir)
+    }
+
+    /// Writes this directory as a zip file to `writer`, returning `writer`. Files keep the mode in
+    /// their [FileMetadata], and directories are given mode 755.
+    pub fn to_zip<W: Write + Seek>(&self, writer: W) -> Result<W, ArchiveError> {
+        let mut zip = ZipWriter::new(writer);
+        let options = SimpleFileOptions::default();
+        self.visit_all(&mut PathBuf::new(), &mut |path, entry| {
+            // Zip files always use `/` as the path separator.
+            let name = Vec::from_iter(path.iter().map(OsStr::to_string_lossy)).join("/");
+            match entry {
+                RawEntry::Dir(_) => zip.add_directory(name, options.unix_permissions(DIR_MODE))?,
+                RawEntry::File(contents, metadata) => {
+                    zip.start_file(name, options.unix_permissions(metadata.mode()))?;
+                    zip.write_all(contents.try_get()?)?;
+                }
+                RawEntry::Symlink(target) => {
+                    zip.add_symlink(name, target.to_string_lossy(), options)?
+                }
+            }
+            Ok(())
+        })?;
+        Ok(zip.finish()?)
+    }
+
+    /// Calls `f` with the path and value of every entry under this directory (including
+    /// directories, which are visited before their contents). Stops at the first error.
+    fn visit_all<F: FnMut(&Path, &RawEntry) -> io::Result<()>>(
+        &self,
+        path: &mut PathBuf,
+        f: &mut F,
+    ) -> io::Result<()> {
+        for (name, entry) in self.0.iter() {
+            path.push(name);
+            f(path, entry)?;
+            if let RawEntry::Dir(dir) = entry {
+                dir.visit_all(path, f)?;
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Adds an entry read from an archive, creating its parent directories as needed. Later
+    /// entries replace earlier ones at the same path (as when extracting an archive), except that
+    /// a directory entry does not replace an existing directory.
+    fn insert_archive_entry(&mut self, path: &Path, entry: RawEntry) -> Result<(), ArchiveError> {
+        let mut names = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::CurDir => {}
+                _ => return Err(ArchiveError::InvalidPath(path.into())),
+            }
+        }
+        let Some((last, parents)) = names.split_last() else {
+            // The archive's root directory.
+            return match entry {
+                RawEntry::Dir(_) => Ok(()),
+                _ => Err(ArchiveError::InvalidPath(path.into())),
+            };
+        };
+        let mut dir = self;
+        for &name in parents {
+            let subdir = dir
+                .0
+                .entry(name.into())
+                .or_insert_with(|| RawEntry::Dir(RawDir::default()));
+            let RawEntry::Dir(subdir) = subdir else {
+                return Err(ArchiveError::InvalidPath(path.into()));
+            };
+            dir = subdir;
+        }
+        if matches!(
+            (&entry, dir.0.get(*last)),
+            (RawEntry::Dir(_), Some(RawEntry::Dir(_)))
+        ) {
+            return Ok(());
+        }
+        dir.0.insert((*last).into(), entry);
+        Ok(())
+    }
+}
+
+/// Error type returned by the archive functions of [RawDir].
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("invalid path {0} in archive")]
+    InvalidPath(PathBuf),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unsupported entry type for {0} in archive")]
+    Unsupported(PathBuf),
+    #[error("unrecognized archive extension: {0}")]
+    UnknownFormat(PathBuf),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn example() -> RawDir {
+        let mut dir = RawDir::default();
+        dir.set_file("Cargo.toml", b"[package]\n".into()).unwrap();
+        dir.set_file("src/main.rs", b"fn main() {}\n".into())
+            .unwrap();
+        dir.set_file("data/binary", vec![0, 1, 2, 255]).unwrap();
+        dir.set_file_with_metadata("configure", b"#!/bin/sh\n".into(), FileMetadata::EXECUTABLE)
+            .unwrap();
+        dir.0
+            .insert("empty".into(), RawEntry::Dir(RawDir::default()));
+        dir.0
+            .insert("link".into(), RawEntry::Symlink("src/main.rs".into()));
+        dir
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let dir = example();
+        let tar = dir.to_tar(vec![]).unwrap();
+        assert_eq!(RawDir::from_tar(tar.as_slice()).unwrap(), dir);
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let dir = example();
+        let zip = dir.to_zip(Cursor::new(vec![])).unwrap();
+        assert_eq!(RawDir::from_zip(zip).unwrap(), dir);
+    }
+
+    #[test]
+    fn invalid_paths() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        // Header::set_path rejects `..`, so write the name directly.
+        header.as_old_mut().name[..7].copy_from_slice(b"../evil");
+        header.set_cksum();
+        builder.append(&header, io::empty()).unwrap();
+        let tar = builder.into_inner().unwrap();
+        assert!(matches!(
+            RawDir::from_tar(tar.as_slice()),
+            Err(ArchiveError::InvalidPath(path)) if path == Path::new("../evil")
+        ));
+        assert!(matches!(
+            RawDir::read_archive("input.rar"),
+            Err(ArchiveError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn format_from_path() {
+        let format = |path: &str| ArchiveFormat::from_path(path.as_ref());
+        assert_eq!(format("a/b.tar"), Some(ArchiveFormat::Tar));
+        assert_eq!(format("b.TAR.GZ"), Some(ArchiveFormat::TarGz));
+        assert_eq!(format("b.tgz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(format("b.zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(format("b.gz"), None);
+        assert_eq!(format("tar"), None);
+    }
+}
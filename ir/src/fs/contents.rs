@@ -0,0 +1,252 @@
+//! [FileContents], the storage for the contents of files in a [RawDir](super::RawDir).
+
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, Read as _, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+/// The contents of a file in a [RawDir](super::RawDir). Contents are shared via an `Arc<[u8]>`,
+/// so cloning a `FileContents` (and therefore a `RawDir`) does not copy them.
+///
+/// Contents loaded with [PopulateOptions::lazy](super::PopulateOptions::lazy) are backed by the
+/// file they were loaded from, and are only read when first accessed (then kept in memory, shared
+/// between all clones), except by [FileContents::write_to]. The `try_*` methods (and the `try_*`
+/// methods of [RawDir](super::RawDir)) return errors reading the file; other accessors (including
+/// `Deref` and `PartialEq`) panic if it cannot be read.
+#[derive(Clone)]
+pub struct FileContents(Backing);
+
+#[derive(Clone)]
+enum Backing {
+    Loaded(Arc<[u8]>),
+    Lazy(Arc<LazyFile>),
+}
+
+struct LazyFile {
+    path: PathBuf,
+    // The file's length when it was populated, used to detect files that changed since.
+    len: u64,
+    loaded: OnceLock<Arc<[u8]>>,
+}
+
+impl FileContents {
+    /// Returns contents that will be read from the file at `path` (which is `len` bytes long)
+    /// when first accessed.
+    pub(super) fn lazy(path: PathBuf, len: u64) -> FileContents {
+        FileContents(Backing::Lazy(Arc::new(LazyFile {
+            path,
+            len,
+            loaded: OnceLock::new(),
+        })))
+    }
+
+    /// Returns the contents, reading them from disk if they have not been read yet. Fails if the
+    /// backing file cannot be read, or has changed size since it was populated.
+    pub fn try_get(&self) -> io::Result<&[u8]> {
+        self.try_shared().map(|contents| &**contents)
+    }
+
+    /// Returns the contents as a shared `Arc<[u8]>`. Panics if they cannot be read (see
+    /// [FileContents::try_get]).
+    pub fn shared(&self) -> Arc<[u8]> {
+        self.try_shared()
+            .unwrap_or_else(|error| panic!("{error}"))
+            .clone()
+    }
+
+    fn try_shared(&self) -> io::Result<&Arc<[u8]>> {
+        let lazy = match &self.0 {
+            Backing::Loaded(contents) => return Ok(contents),
+            Backing::Lazy(lazy) => lazy,
+        };
+        if let Some(contents) = lazy.loaded.get() {
+            return Ok(contents);
+        }
+        let contents = std::fs::read(&lazy.path).map_err(|error| with_path(error, &lazy.path))?;
+        if u64::try_from(contents.len()) != Ok(lazy.len) {
+            return Err(changed(&lazy.path));
+        }
+        // If another thread loaded the contents first, its copy is kept and ours is dropped.
+        Ok(lazy.loaded.get_or_init(|| contents.into()))
+    }
+
+    /// Writes the contents to `out`. Unlike the other accessors, this does not keep contents that
+    /// have not been read yet in memory: they are streamed from the backing file instead (and so
+    /// are read from it again the next time they are accessed). Fails in the same cases as
+    /// [FileContents::try_get], or if writing to `out` fails.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let lazy = match &self.0 {
+            Backing::Lazy(lazy) if lazy.loaded.get().is_none() => lazy,
+            _ => return out.write_all(self.try_get()?),
+        };
+        let mut file = File::open(&lazy.path).map_err(|error| with_path(error, &lazy.path))?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut remaining = lazy.len;
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(with_path(error, &lazy.path)),
+            };
+            remaining = remaining
+                .checked_sub(read.try_into().unwrap())
+                .ok_or_else(|| changed(&lazy.path))?;
+            out.write_all(&buffer[..read])?;
+        }
+        match remaining {
+            0 => Ok(()),
+            _ => Err(changed(&lazy.path)),
+        }
+    }
+
+    /// Compares the contents with `other`'s, like `==`, but returns an error rather than panicking
+    /// if either cannot be read. Contents that are shared are equal without being read.
+    pub fn try_eq(&self, other: &FileContents) -> io::Result<bool> {
+        let shared = match (&self.0, &other.0) {
+            (Backing::Loaded(a), Backing::Loaded(b)) => Arc::ptr_eq(a, b),
+            (Backing::Lazy(a), Backing::Lazy(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        Ok(shared || (self.len() == other.len() && self.try_get()? == other.try_get()?))
+    }
+
+    /// Returns whether the contents are in memory (i.e. accessing them will not read a file).
+    pub fn is_loaded(&self) -> bool {
+        match &self.0 {
+            Backing::Loaded(_) => true,
+            Backing::Lazy(lazy) => lazy.loaded.get().is_some(),
+        }
+    }
+
+    /// Returns the length of the contents in bytes, without reading them.
+    pub fn len(&self) -> u64 {
+        match &self.0 {
+            Backing::Loaded(contents) => contents.len().try_into().unwrap(),
+            Backing::Lazy(lazy) => lazy.len,
+        }
+    }
+
+    /// Returns true if the contents are empty, without reading them.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Returns the error for a lazily-loaded file at `path` whose size changed since it was populated.
+fn changed(path: &Path) -> io::Error {
+    with_path(
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "file changed since it was loaded",
+        ),
+        path,
+    )
+}
+
+/// Adds `path` to the message of `error`.
+fn with_path(error: io::Error, path: &Path) -> io::Error {
+    io::Error::new(
+        error.kind(),
+        format!("failed to read {}: {error}", path.display()),
+    )
+}
+
+impl Deref for FileContents {
+    type Target = [u8];
+
+    /// Returns the contents. Panics if they cannot be read (see [FileContents::try_get]).
+    fn deref(&self) -> &[u8] {
+        self.try_get().unwrap_or_else(|error| panic!("{error}"))
+    }
+}
+
+impl AsRef<[u8]> for FileContents {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for FileContents {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.0 {
+            Backing::Loaded(contents) => Debug::fmt(contents, f),
+            Backing::Lazy(lazy) => match lazy.loaded.get() {
+                Some(contents) => Debug::fmt(contents, f),
+                None => write!(f, "<{}B from {}>", lazy.len, lazy.path.display()),
+            },
+        }
+    }
+}
+
+impl PartialEq for FileContents {
+    /// Compares the contents. Contents that are shared (e.g. between clones of a `RawDir`) are
+    /// equal without being read. Panics if the contents cannot be read (see
+    /// [FileContents::try_eq]).
+    fn eq(&self, other: &FileContents) -> bool {
+        self.try_eq(other).unwrap_or_else(|error| panic!("{error}"))
+    }
+}
+
+impl Eq for FileContents {}
+
+impl From<Vec<u8>> for FileContents {
+    fn from(contents: Vec<u8>) -> FileContents {
+        FileContents(Backing::Loaded(contents.into()))
+    }
+}
+
+impl From<Arc<[u8]>> for FileContents {
+    fn from(contents: Arc<[u8]>) -> FileContents {
+        FileContents(Backing::Loaded(contents))
+    }
+}
+
+impl From<&[u8]> for FileContents {
+    fn from(contents: &[u8]) -> FileContents {
+        FileContents(Backing::Loaded(contents.into()))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for FileContents {
+    fn from(contents: &[u8; N]) -> FileContents {
+        contents.as_slice().into()
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, b"abc").unwrap();
+        let contents = FileContents::lazy(path.clone(), 3);
+        let clone = contents.clone();
+        assert!(!contents.is_loaded());
+        assert_eq!(contents.len(), 3);
+        assert_eq!(&*contents, b"abc");
+        // Clones share the loaded contents.
+        assert!(clone.is_loaded());
+        assert!(Arc::ptr_eq(&contents.shared(), &clone.shared()));
+        assert_eq!(contents, FileContents::from(b"abc"));
+
+        // Errors are reported when the file is read.
+        assert_eq!(
+            FileContents::lazy(path.clone(), 4)
+                .try_get()
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            FileContents::lazy(path, 3).try_get().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Comparing [RawDir]s and applying the differences between them.
+
+use super::{FileContents, FileMetadata, RawDir, RawEntry};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter, Write as _};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// The differences between two [RawDir]s, as returned by [RawDir::diff]. Directories are implied
+/// by the files and symlinks they contain, so a [Diff] does not describe empty directories.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+    /// The changed files and symlinks, sorted by path.
+    pub changes: Vec<Change>,
+}
+
+/// A change to a single file or symlink.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// Path of the changed entry, relative to the root of the [RawDir].
+    pub path: PathBuf,
+    /// The entry before the change, or `None` if it was added.
+    pub old: Option<Leaf>,
+    /// The entry after the change, or `None` if it was removed.
+    pub new: Option<Leaf>,
+}
+
+/// A non-directory entry in a [RawDir].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Leaf {
+    File(FileContents, FileMetadata),
+    Symlink(PathBuf),
+}
+
+impl Leaf {
+    fn from_entry(entry: &RawEntry) -> Option<Leaf> {
+        match entry {
+            RawEntry::Dir(_) => None,
+            RawEntry::File(contents, metadata) => Some(Leaf::File(contents.clone(), *metadata)),
+            RawEntry::Symlink(target) => Some(Leaf::Symlink(target.clone())),
+        }
+    }
+
+    fn into_entry(self) -> RawEntry {
+        match self {
+            Leaf::File(contents, metadata) => RawEntry::File(contents, metadata),
+            Leaf::Symlink(target) => RawEntry::Symlink(target),
+        }
+    }
+
+    /// Returns this entry's contents as text for a unified diff, or `None` if it is binary. Like
+    /// git, files containing NUL bytes are considered binary.
+    fn text(&self) -> Option<String> {
+        match self {
+            Leaf::File(contents, _) if contents.contains(&0) => None,
+            Leaf::File(contents, _) => str::from_utf8(contents).ok().map(Into::into),
+            Leaf::Symlink(target) => Some(format!("symlink to {}\n", target.display())),
+        }
+    }
+
+    /// Compares this leaf with `other`, returning an error if file contents cannot be read.
+    fn try_eq(&self, other: &Leaf) -> io::Result<bool> {
+        match (self, other) {
+            (Leaf::File(a, a_meta), Leaf::File(b, b_meta)) => Ok(a_meta == b_meta && a.try_eq(b)?),
+            (Leaf::Symlink(a), Leaf::Symlink(b)) => Ok(a == b),
+            _ => Ok(false),
+        }
+    }
+
+    /// Compares this leaf with `entry`, returning an error if file contents cannot be read.
+    fn try_matches(&self, entry: &RawEntry) -> io::Result<bool> {
+        match (self, entry) {
+            (Leaf::File(a, a_meta), RawEntry::File(b, b_meta)) => {
+                Ok(a_meta == b_meta && a.try_eq(b)?)
+            }
+            (Leaf::Symlink(a), RawEntry::Symlink(b)) => Ok(a == b),
+            _ => Ok(false),
+        }
+    }
+}
+
+impl Change {
+    /// Returns this change in unified diff format, with `a/` and `b/` path prefixes (like `git
+    /// diff`). Binary files are described but not diffed, and mode changes are described on a
+    /// separate line.
+    pub fn unified_diff(&self) -> String {
+        let path = self.path.display();
+        let mut out = String::new();
+        if let (Some(Leaf::File(_, old)), Some(Leaf::File(_, new))) = (&self.old, &self.new)
+            && old != new
+        {
+            let _ = writeln!(
+                out,
+                "mode change {:o} => {:o} {path}",
+                old.mode(),
+                new.mode()
+            );
+        }
+        let old_name = self.old.as_ref().map(|_| format!("a/{path}"));
+        let new_name = self.new.as_ref().map(|_| format!("b/{path}"));
+        let old_name = old_name.as_deref().unwrap_or("/dev/null");
+        let new_name = new_name.as_deref().unwrap_or("/dev/null");
+        let text = |leaf: &Option<Leaf>| leaf.as_ref().map_or(Some(String::new()), Leaf::text);
+        match (text(&self.old), text(&self.new)) {
+            (Some(old), Some(new)) => {
+                let diff = TextDiff::from_lines(&old, &new);
+                let _ = write!(out, "{}", diff.unified_diff().header(old_name, new_name));
+            }
+            // Only the mode of a binary file changed.
+            _ if matches!((&self.old, &self.new),
+                (Some(Leaf::File(a, _)), Some(Leaf::File(b, _))) if a == b) => {}
+            _ => {
+                let _ = writeln!(out, "Binary files {old_name} and {new_name} differ");
+            }
+        }
+        out
+    }
+}
+
+impl Display for Diff {
+    /// Formats every change as a unified diff.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for change in &self.changes {
+            f.write_str(&change.unified_diff())?;
+        }
+        Ok(())
+    }
+}
+
+impl RawDir {
+    /// Returns the changes needed to turn `self` into `other`. Files whose contents are shared
+    /// between `self` and `other` (e.g. because one is a modified clone of the other) are not read;
+    /// other lazily-loaded files are, and this panics if one cannot be read (see
+    /// [RawDir::try_diff]).
+    pub fn diff(&self, other: &RawDir) -> Diff {
+        self.try_diff(other)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Returns the changes needed to turn `self` into `other` (see [RawDir::diff]), or an error if
+    /// a lazily-loaded file cannot be read. The contents of the changed files are read, so
+    /// formatting the returned [Diff] does not fail.
+    pub fn try_diff(&self, other: &RawDir) -> io::Result<Diff> {
+        let old = leaves(self);
+        let mut new = leaves(other);
+        let mut changes = vec![];
+        for (path, old) in old {
+            match new.remove(&path) {
+                Some(new) if new.try_eq(&old)? => {}
+                new => changes.push(Change {
+                    path,
+                    old: Some(old),
+                    new,
+                }),
+            }
+        }
+        changes.extend(new.into_iter().map(|(path, new)| Change {
+            path,
+            old: None,
+            new: Some(new),
+        }));
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        for change in &changes {
+            for leaf in [&change.old, &change.new] {
+                if let Some(Leaf::File(contents, _)) = leaf {
+                    contents.try_get()?;
+                }
+            }
+        }
+        Ok(Diff { changes })
+    }
+
+    /// Applies `diff` to this directory. Each change's `old` entry must match the current entry at
+    /// that path (with `None` meaning there is no entry). Directories left empty by removals are
+    /// removed.
+    ///
+    /// The patch is applied atomically: if an error is returned, `self` is not modified.
+    /// Lazily-loaded files that cannot be read are reported as [PatchError::Unreadable].
+    pub fn apply_patch(&mut self, diff: &Diff) -> Result<(), PatchError> {
+        for change in &diff.changes {
+            let current = self.entry(&change.path)?;
+            let matches = match (&change.old, current) {
+                (None, None) => true,
+                (Some(old), Some(current)) => {
+                    old.try_matches(current)
+                        .map_err(|error| PatchError::Unreadable {
+                            path: change.path.clone(),
+                            kind: error.kind(),
+                        })?
+                }
+                _ => false,
+            };
+            if !matches {
+                return Err(PatchError::Conflict(change.path.clone()));
+            }
+        }
+        // Apply the changes to a copy, so that an error partway through (e.g. a file being added
+        // under a path that is still a file) does not leave `self` half-patched. Removals are
+        // applied first so that an entry can change between being a file and a directory.
+        let mut patched = self.clone();
+        let (removals, writes): (Vec<_>, Vec<_>) =
+            diff.changes.iter().partition(|c| c.new.is_none());
+        for change in removals.into_iter().chain(writes) {
+            patched.replace_entry(&change.path, change.new.clone().map(Leaf::into_entry))?;
+        }
+        *self = patched;
+        Ok(())
+    }
+
+    /// Returns the entry at `path`, without following symlinks.
+    fn entry(&self, path: &Path) -> Result<Option<&RawEntry>, PatchError> {
+        let mut names = normal_components(path)?;
+        let last = names.pop().expect("normal_components returned no names");
+        let mut dir = self;
+        for name in names {
+            match dir.0.get(name) {
+                Some(RawEntry::Dir(subdir)) => dir = subdir,
+                _ => return Ok(None),
+            }
+        }
+        Ok(dir.0.get(last))
+    }
+
+    /// Replaces the entry at `path` with `entry`, or removes it if `entry` is `None`. Creates
+    /// parent directories as needed, and removes directories left empty by a removal.
+    fn replace_entry(&mut self, path: &Path, entry: Option<RawEntry>) -> Result<(), PatchError> {
+        let names = normal_components(path)?;
+        let (last, parents) = names.split_last().expect("no names");
+        match entry {
+            Some(entry) => {
+                let mut dir = self;
+                for &name in parents {
+                    let subdir = dir
+                        .0
+                        .entry(name.into())
+                        .or_insert_with(|| RawEntry::Dir(RawDir::default()));
+                    let RawEntry::Dir(subdir) = subdir else {
+                        return Err(PatchError::UnderFile(path.into()));
+                    };
+                    dir = subdir;
+                }
+                dir.0.insert((*last).into(), entry);
+            }
+            None => {
+                fn remove(dir: &mut RawDir, names: &[&std::ffi::OsStr]) {
+                    let [first, rest @ ..] = names else { return };
+                    if rest.is_empty() {
+                        dir.0.remove(*first);
+                        return;
+                    }
+                    if let Some(RawEntry::Dir(subdir)) = dir.0.get_mut(*first) {
+                        remove(subdir, rest);
+                        if subdir.0.is_empty() {
+                            dir.0.remove(*first);
+                        }
+                    }
+                }
+                remove(self, &names);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the names of the components of `path`, which must be a non-empty relative path with no
+/// `.` or `..` components.
+fn normal_components(path: &Path) -> Result<Vec<&std::ffi::OsStr>, PatchError> {
+    let names: Option<Vec<_>> = path
+        .components()
+        .map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    match names {
+        Some(names) if !names.is_empty() => Ok(names),
+        _ => Err(PatchError::InvalidPath(path.into())),
+    }
+}
+
+/// Returns every file and symlink in `dir`, keyed by path.
+fn leaves(dir: &RawDir) -> BTreeMap<PathBuf, Leaf> {
+    let mut out = BTreeMap::new();
+    dir.visit(&mut PathBuf::new(), &mut |path, entry| {
+        if let Some(leaf) = Leaf::from_entry(entry) {
+            out.insert(path.to_path_buf(), leaf);
+        }
+    });
+    out
+}
+
+/// Error type returned by [RawDir::apply_patch].
+#[derive(Debug, Eq, Hash, PartialEq, Error)]
+pub enum PatchError {
+    #[error("{0} does not match the patch's original contents")]
+    Conflict(PathBuf),
+    #[error("invalid path {0} in patch")]
+    InvalidPath(PathBuf),
+    #[error("cannot add {0}: a parent directory is a file")]
+    UnderFile(PathBuf),
+    /// A lazily-loaded file (see [PopulateOptions::lazy](super::PopulateOptions::lazy)) could not
+    /// be read to compare it with the patch.
+    #[error("failed to read {path}: {kind}")]
+    Unreadable { path: PathBuf, kind: io::ErrorKind },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(files: &[(&str, &str)]) -> RawDir {
+        let mut dir = RawDir::default();
+        for (path, contents) in files {
+            dir.set_file(path, contents.as_bytes().into()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn diff_and_patch() {
+        let old = dir(&[
+            ("Cargo.toml", "[package]\nname = \"a\"\n"),
+            ("src/lib.rs", "fn a() {}\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("x", "file becomes directory\n"),
+        ]);
+        let mut new = dir(&[
+            ("Cargo.toml", "[package]\nname = \"b\"\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("x/y", "\u{0}"),
+        ]);
+        new.0.insert("link".into(), RawEntry::Symlink("src".into()));
+        let diff = old.diff(&new);
+        let summary = Vec::from_iter(
+            diff.changes
+                .iter()
+                .map(|c| (c.path.to_str().unwrap(), c.old.is_some(), c.new.is_some())),
+        );
+        assert_eq!(
+            summary,
+            [
+                ("Cargo.toml", true, true),
+                ("link", false, true),
+                ("src/lib.rs", true, false),
+                ("x", true, false),
+                ("x/y", false, true),
+            ]
+        );
+        assert_eq!(
+            diff.changes[0].unified_diff(),
+            "--- a/Cargo.toml\n+++ b/Cargo.toml\n@@ -1,2 +1,2 @@\n [package]\n-name = \"a\"\n+name = \"b\"\n"
+        );
+        assert_eq!(
+            diff.changes[1].unified_diff(),
+            "--- /dev/null\n+++ b/link\n@@ -0,0 +1 @@\n+symlink to src\n"
+        );
+        assert_eq!(
+            diff.changes[4].unified_diff(),
+            "Binary files /dev/null and b/x/y differ\n"
+        );
+        assert!(old.diff(&old).changes.is_empty());
+        let mut executable = RawDir::default();
+        executable
+            .set_file_with_metadata(
+                "x",
+                b"file becomes directory\n".into(),
+                FileMetadata::EXECUTABLE,
+            )
+            .unwrap();
+        let mode_change = dir(&[("x", "file becomes directory\n")]).diff(&executable);
+        assert_eq!(mode_change.to_string(), "mode change 644 => 755 x\n");
+
+        let mut patched = old.clone();
+        patched.apply_patch(&diff).unwrap();
+        assert_eq!(patched, new);
+        // The patch no longer applies, and a failed patch leaves the directory unchanged.
+        assert_eq!(
+            patched.apply_patch(&diff),
+            Err(PatchError::Conflict("Cargo.toml".into()))
+        );
+        assert_eq!(patched, new);
+
+        let mut patched = dir(&[("src/lib.rs", "fn a() {}\n")]);
+        let removal = Diff {
+            changes: vec![diff.changes[2].clone()],
+        };
+        patched.apply_patch(&removal).unwrap();
+        assert_eq!(patched, RawDir::default());
+        let invalid = Diff {
+            changes: vec![Change {
+                path: "../x".into(),
+                old: None,
+                new: None,
+            }],
+        };
+        assert_eq!(
+            patched.apply_patch(&invalid),
+            Err(PatchError::InvalidPath("../x".into()))
+        );
+    }
+
+    /// Lazily-loaded files that can no longer be read are reported instead of panicking.
+    #[cfg(not(miri))]
+    #[test]
+    fn patch_unreadable() {
+        use crate::fs::PopulateOptions;
+        let source = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("main.c"), b"int main;\n").unwrap();
+        let options = PopulateOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        let mut lazy = RawDir::populate_with(std::fs::read_dir(source.path()).unwrap(), &options)
+            .unwrap()
+            .dir;
+        let diff = dir(&[("main.c", "int main;\n")]).diff(&RawDir::default());
+        std::fs::remove_file(source.path().join("main.c")).unwrap();
+        assert_eq!(
+            lazy.apply_patch(&diff),
+            Err(PatchError::Unreadable {
+                path: "main.c".into(),
+                kind: io::ErrorKind::NotFound,
+            })
+        );
+    }
+}
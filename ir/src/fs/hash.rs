@@ -0,0 +1,172 @@
+//! Content hashing for [RawDir]s.
+
+use super::{RawDir, RawEntry};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A SHA-256 hash of the contents of a [RawDir] or [RawEntry], as returned by
+/// [RawDir::content_hash]. Displays as lowercase hex.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ContentHash([u8; 32]);
+
+impl RawDir {
+    /// Returns a hash of this directory's contents. This is a Merkle hash: each directory's hash
+    /// is computed from the names and hashes of its entries, so two directories have the same hash
+    /// if and only if they have the same contents (file names, file contents and modes, and symlink
+    /// targets).
+    ///
+    /// The hash does not depend on the platform or on how the [RawDir] was created, so it can be
+    /// used as a stable identifier (e.g. as a key in a content-addressed store).
+    ///
+    /// Panics if a lazily-loaded file cannot be read (see [RawDir::try_content_hash]).
+    pub fn content_hash(&self) -> ContentHash {
+        self.try_content_hash()
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Returns a hash of this directory's contents (see [RawDir::content_hash]), or an error if a
+    /// lazily-loaded file cannot be read.
+    pub fn try_content_hash(&self) -> io::Result<ContentHash> {
+        let mut hasher = Sha256::new();
+        hasher.update(b"dir\0");
+        for (name, entry) in &self.0 {
+            let name = name.as_encoded_bytes();
+            hasher.update(u64::try_from(name.len()).unwrap().to_le_bytes());
+            hasher.update(name);
+            hasher.update(entry.try_content_hash()?.0);
+        }
+        Ok(ContentHash(hasher.finalize().into()))
+    }
+}
+
+impl RawEntry {
+    /// Returns a hash of this entry's contents (including file metadata). See
+    /// [RawDir::content_hash]. Panics if a lazily-loaded file cannot be read.
+    pub fn content_hash(&self) -> ContentHash {
+        self.try_content_hash()
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Returns a hash of this entry's contents, or an error if a lazily-loaded file cannot be
+    /// read.
+    pub fn try_content_hash(&self) -> io::Result<ContentHash> {
+        let mut hasher = Sha256::new();
+        match self {
+            RawEntry::Dir(dir) => return dir.try_content_hash(),
+            RawEntry::File(contents, metadata) => {
+                hasher.update(b"file\0");
+                hasher.update(metadata.mode.to_le_bytes());
+                // Stream the contents, so that lazily-loaded files are not kept in memory.
+                contents.write_to(&mut HashWriter(&mut hasher))?;
+            }
+            RawEntry::Symlink(target) => {
+                hasher.update(b"symlink\0");
+                hasher.update(target.as_os_str().as_encoded_bytes());
+            }
+        }
+        Ok(ContentHash(hasher.finalize().into()))
+    }
+}
+
+/// Adapts a [Sha256] to [io::Write].
+struct HashWriter<'h>(&'h mut Sha256);
+
+impl io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Debug for ContentHash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ContentHash({self})")
+    }
+}
+
+impl Display for ContentHash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<ContentHash, ParseHashError> {
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseHashError);
+        }
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| ParseHashError)?;
+        }
+        Ok(ContentHash(hash))
+    }
+}
+
+/// Error returned when parsing a [ContentHash] from a string that is not 64 hex digits.
+#[derive(Debug, Eq, Hash, PartialEq, Error)]
+#[error("invalid content hash")]
+pub struct ParseHashError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::FileMetadata;
+
+    #[test]
+    fn content_hash() {
+        let mut a = RawDir::default();
+        a.set_file("src/main.c", b"int main() {}\n".into()).unwrap();
+        a.set_file("README", b"".into()).unwrap();
+        let mut b = RawDir::default();
+        b.set_file("README", b"".into()).unwrap();
+        b.set_file("src/main.c", b"int main() {}\n".into()).unwrap();
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), RawDir::default().content_hash());
+
+        // Changing a file's contents, or moving it, changes the hash.
+        let mut b = RawDir::default();
+        b.set_file("README", b"".into()).unwrap();
+        b.set_file("src/main.c", b"int main() { }\n".into())
+            .unwrap();
+        assert_ne!(a.content_hash(), b.content_hash());
+        let mut c = RawDir::default();
+        c.set_file("README", b"".into()).unwrap();
+        c.set_file("main.c", b"int main() {}\n".into()).unwrap();
+        assert_ne!(a.content_hash(), c.content_hash());
+        // Making a file executable changes the hash.
+        let mut d = RawDir::default();
+        d.set_file("README", b"".into()).unwrap();
+        d.set_file_with_metadata(
+            "src/main.c",
+            b"int main() {}\n".into(),
+            FileMetadata::EXECUTABLE,
+        )
+        .unwrap();
+        assert_ne!(a.content_hash(), d.content_hash());
+        // A symlink does not hash the same as a file containing its target.
+        let link = RawEntry::Symlink("README".into());
+        assert_ne!(
+            link.content_hash(),
+            RawEntry::File(b"README".into(), Default::default()).content_hash()
+        );
+
+        // The hash is stable.
+        assert_eq!(
+            RawDir::default().content_hash().to_string(),
+            "34312174686ce443db57c7fda1233d8a4615dd0ac30168116fcf35fee068e0ba"
+        );
+        let hash = a.content_hash();
+        assert_eq!(hash.to_string().parse(), Ok(hash));
+        assert_eq!("xyz".parse::<ContentHash>(), Err(ParseHashError));
+    }
+}
@@ -0,0 +1,1164 @@
+//! Types representing a filesystem. Example use cases: representing a C source project, a Cargo
+//! project, etc.
+
+mod archive;
+mod contents;
+mod diff;
+mod hash;
+mod store;
+
+pub use archive::{ArchiveError, ArchiveFormat};
+pub use contents::FileContents;
+pub use diff::{Change, Diff, Leaf, PatchError};
+pub use hash::{ContentHash, ParseHashError};
+pub use store::ObjectStore;
+
+use serde::de::Error as _;
+use serde::ser::{Error as _, SerializeMap as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, VecDeque, btree_map};
+use std::ffi::OsString;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::ReadDir;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// A representation of a file-system directory entry.
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum RawEntry {
+    Dir(RawDir),
+    /// A regular file, containing the file's contents and metadata.
+    File(FileContents, FileMetadata),
+    /// A symbolic link, containing the link's target path (which may be relative or absolute).
+    Symlink(PathBuf),
+}
+
+impl RawEntry {
+    fn dir(&self) -> Option<&RawDir> {
+        match self {
+            RawEntry::Dir(raw_dir) => Some(raw_dir),
+            _ => None,
+        }
+    }
+
+    fn file(&self) -> Option<&FileContents> {
+        match self {
+            RawEntry::File(file, _) => Some(file),
+            _ => None,
+        }
+    }
+
+    fn symlink(&self) -> Option<&Path> {
+        match self {
+            RawEntry::Symlink(target) => Some(target),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata of a file in a [RawDir].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FileMetadata {
+    // Always either 0o644 or 0o755 (see FileMetadata::mode).
+    mode: u32,
+}
+
+impl FileMetadata {
+    /// Metadata for an executable file (mode `0o755`).
+    pub const EXECUTABLE: FileMetadata = FileMetadata { mode: 0o755 };
+
+    /// Returns the file's Unix permission bits. Modes are always normalized to either `0o644` or
+    /// (for files that are executable by anyone) `0o755`, as git does, so that they do not depend
+    /// on the umask or owner of the loaded files, and so that materializing and reloading a
+    /// [RawDir] preserves its [content hash](RawDir::content_hash).
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Returns true if the file is executable by anyone.
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
+    }
+
+    /// Returns metadata with the given mode, normalized to `0o644` or `0o755` (see
+    /// [FileMetadata::mode]).
+    pub fn normalized(mode: u32) -> FileMetadata {
+        match mode & 0o111 {
+            0 => FileMetadata::default(),
+            _ => FileMetadata::EXECUTABLE,
+        }
+    }
+
+    /// Returns the (normalized) metadata of a file on disk.
+    fn from_fs(metadata: &std::fs::Metadata) -> FileMetadata {
+        #[cfg(unix)]
+        return FileMetadata::normalized(std::os::unix::fs::PermissionsExt::mode(
+            &metadata.permissions(),
+        ));
+        #[cfg(not(unix))]
+        FileMetadata::default()
+    }
+}
+
+impl Default for FileMetadata {
+    /// Metadata for a regular, non-executable file (mode `0o644`).
+    fn default() -> FileMetadata {
+        FileMetadata { mode: 0o644 }
+    }
+}
+
+/// How [RawDir::populate_with] handles symbolic links.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Replace each symlink with a copy of the file or directory it points to. Dangling symlinks
+    /// and symlink loops are errors.
+    Follow,
+    /// Store each symlink as a [RawEntry::Symlink].
+    #[default]
+    Preserve,
+    /// Return an error if a symlink is found.
+    Reject,
+}
+
+/// Decides whether [RawDir::populate_with] should load an entry. It is passed the entry's path
+/// (relative to the directory being loaded) and whether the entry is a directory, and returns
+/// `true` to load it.
+pub type PopulateFilter = Arc<dyn Fn(&Path, bool) -> bool + Send + Sync>;
+
+/// Options for [RawDir::populate_with].
+#[derive(Clone, Default)]
+pub struct PopulateOptions {
+    /// How to handle symbolic links.
+    pub symlinks: SymlinkPolicy,
+
+    /// If set, only entries for which this returns `true` are loaded. Directories that are
+    /// filtered out are skipped entirely.
+    pub filter: Option<PopulateFilter>,
+
+    /// If set, files larger than this many bytes are skipped.
+    pub max_file_size: Option<u64>,
+
+    /// If true, file contents are not read while populating; instead, each file is read when its
+    /// contents are first accessed (see [FileContents]). The loaded files must not be changed or
+    /// removed until then.
+    pub lazy: bool,
+}
+
+impl Debug for PopulateOptions {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("PopulateOptions")
+            .field("symlinks", &self.symlinks)
+            .field("filter", &self.filter.as_ref().map(|_| ".."))
+            .field("max_file_size", &self.max_file_size)
+            .field("lazy", &self.lazy)
+            .finish()
+    }
+}
+
+/// The result of [RawDir::populate_with].
+#[derive(Debug)]
+pub struct Populated {
+    pub dir: RawDir,
+    /// Number of directories loaded (not counting the top-level directory).
+    pub directories: usize,
+    /// Number of files loaded.
+    pub files: usize,
+    /// Entries that were not loaded because of the [PopulateOptions], sorted by path. Paths are
+    /// relative to the directory being loaded.
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+}
+
+/// Why [RawDir::populate_with] skipped an entry.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SkipReason {
+    /// [PopulateOptions::filter] returned `false`.
+    Filtered,
+    /// The file was larger than [PopulateOptions::max_file_size]. Contains the file's size.
+    TooLarge(u64),
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Filtered => write!(f, "filtered out"),
+            SkipReason::TooLarge(size) => write!(f, "too large ({size}B)"),
+        }
+    }
+}
+
+/// The maximum number of symlinks [RawDir::get_file] and [RawDir::set_file] will follow when
+/// resolving a single path (the same limit Linux uses).
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// A representation of a file-system directory tree.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct RawDir(BTreeMap<OsString, RawEntry>);
+
+impl RawDir {
+    /// Create a [RawDir] from a local file system directory, using the default
+    /// [PopulateOptions] (which preserve symlinks).
+    ///
+    /// Returns the [RawDir], number of directories and number of
+    /// files, as a tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_dir` - a [ReadDir] iterator over a file-system
+    ///   directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use harvest_ir::fs::RawDir;
+    /// # #[cfg(miri)] fn main() {}
+    /// # #[cfg(not(miri))]
+    /// # fn main() -> std::io::Result<()> {
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let path = dir.path();
+    /// let (raw_dir, num_dirs, num_files) = RawDir::populate_from(std::fs::read_dir(path)?)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn populate_from(read_dir: ReadDir) -> std::io::Result<(Self, usize, usize)> {
+        let populated = RawDir::populate_with(read_dir, &PopulateOptions::default())?;
+        Ok((populated.dir, populated.directories, populated.files))
+    }
+
+    /// Like [RawDir::populate_from], but with the given `options`. Also returns the entries that
+    /// were skipped because of `options`.
+    pub fn populate_with(
+        read_dir: ReadDir,
+        options: &PopulateOptions,
+    ) -> std::io::Result<Populated> {
+        let mut populated = Populated {
+            dir: RawDir::default(),
+            directories: 0,
+            files: 0,
+            skipped: vec![],
+        };
+        populated.dir = RawDir::populate_inner(
+            read_dir,
+            options,
+            &mut PathBuf::new(),
+            &mut vec![],
+            &mut populated,
+        )?;
+        // Directory iteration order is unspecified, so sort for deterministic output.
+        populated.skipped.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(populated)
+    }
+
+    /// Implementation of populate_with. `relative` is the path of `read_dir` relative to the
+    /// top-level directory, and `ancestors` contains the canonical paths of the directories being
+    /// populated (used to detect symlink loops). Counts and skipped entries are accumulated into
+    /// `populated`.
+    fn populate_inner(
+        read_dir: ReadDir,
+        options: &PopulateOptions,
+        relative: &mut PathBuf,
+        ancestors: &mut Vec<PathBuf>,
+        populated: &mut Populated,
+    ) -> std::io::Result<Self> {
+        let mut result = BTreeMap::default();
+        for entry in read_dir {
+            let entry = entry?;
+            let path = entry.path();
+            relative.push(entry.file_name());
+            let mut metadata = entry.metadata()?;
+            if metadata.is_symlink() {
+                match options.symlinks {
+                    SymlinkPolicy::Follow => metadata = std::fs::metadata(&path)?,
+                    SymlinkPolicy::Preserve => {
+                        if options.filter.as_ref().is_some_and(|f| !f(relative, false)) {
+                            populated
+                                .skipped
+                                .push((relative.clone(), SkipReason::Filtered));
+                        } else {
+                            let target = std::fs::read_link(&path)?;
+                            result.insert(entry.file_name(), RawEntry::Symlink(target));
+                        }
+                        relative.pop();
+                        continue;
+                    }
+                    SymlinkPolicy::Reject => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("symlink found at {}", path.display()),
+                        ));
+                    }
+                }
+            }
+            if options
+                .filter
+                .as_ref()
+                .is_some_and(|f| !f(relative, metadata.is_dir()))
+            {
+                populated
+                    .skipped
+                    .push((relative.clone(), SkipReason::Filtered));
+            } else if metadata.is_dir() {
+                let canonical = std::fs::canonicalize(&path)?;
+                if ancestors.contains(&canonical) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("symlink loop at {}", path.display()),
+                    ));
+                }
+                ancestors.push(canonical);
+                let read_dir = std::fs::read_dir(&path)?;
+                let subdir =
+                    RawDir::populate_inner(read_dir, options, relative, ancestors, populated)?;
+                ancestors.pop();
+                populated.directories += 1;
+                result.insert(entry.file_name(), RawEntry::Dir(subdir));
+            } else if let Some(size) = options
+                .max_file_size
+                .filter(|&max| metadata.is_file() && metadata.len() > max)
+                .map(|_| metadata.len())
+            {
+                populated
+                    .skipped
+                    .push((relative.clone(), SkipReason::TooLarge(size)));
+            } else if metadata.is_file() {
+                let contents = match options.lazy {
+                    false => std::fs::read(&path)?.into(),
+                    true => FileContents::lazy(std::fs::canonicalize(&path)?, metadata.len()),
+                };
+                let metadata = FileMetadata::from_fs(&metadata);
+                result.insert(entry.file_name(), RawEntry::File(contents, metadata));
+                populated.files += 1;
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} is not a file, directory, or symlink", path.display()),
+                ));
+            }
+            relative.pop();
+        }
+        Ok(RawDir(result))
+    }
+
+    /// Print a representation of the directory to standard out.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The level of this directory relative to the
+    ///   root. Used to add padding to before entry names.
+    pub fn display(&self, level: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pad = "  ".repeat(level);
+        for (name, entry) in self
+            .0
+            .iter()
+            .filter_map(|(name, entry)| entry.dir().map(|e| (name, e)))
+        {
+            writeln!(f, "{pad}{}", name.to_string_lossy())?;
+            entry.display(1, f)?;
+        }
+
+        for (name, entry) in self
+            .0
+            .iter()
+            .filter_map(|(name, entry)| entry.file().map(|e| (name, e)))
+        {
+            writeln!(f, "{pad}{} ({}B)", name.to_string_lossy(), entry.len())?;
+        }
+
+        for (name, target) in self
+            .0
+            .iter()
+            .filter_map(|(name, entry)| entry.symlink().map(|e| (name, e)))
+        {
+            writeln!(f, "{pad}{} -> {}", name.to_string_lossy(), target.display())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the path and contents of the files in this directory and its subdirectories. Paths
+    /// are relative to this directory. Symlinks are not included (see
+    /// [RawDir::symlinks_recursive]).
+    ///
+    /// Panics if a lazily-loaded file cannot be read (see [RawDir::try_files_recursive]).
+    pub fn files_recursive(&self) -> Vec<(PathBuf, &[u8])> {
+        self.try_files_recursive()
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Returns the path and contents of the files in this directory and its subdirectories (see
+    /// [RawDir::files_recursive]), or the first error reading a lazily-loaded file.
+    pub fn try_files_recursive(&self) -> io::Result<Vec<(PathBuf, &[u8])>> {
+        let mut out = vec![];
+        self.visit(&mut PathBuf::new(), &mut |path, entry| {
+            if let RawEntry::File(contents, _) = entry {
+                out.push((path.to_path_buf(), contents));
+            }
+        });
+        out.into_iter()
+            .map(|(path, contents)| Ok((path, contents.try_get()?)))
+            .collect()
+    }
+
+    /// Reads the contents of every lazily-loaded file in this directory (see
+    /// [PopulateOptions::lazy]) that has not been read yet, so that accessing them later cannot
+    /// fail. Returns the first error encountered.
+    pub fn load_contents(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        self.visit(&mut PathBuf::new(), &mut |_, entry| {
+            if let RawEntry::File(contents, _) = entry
+                && result.is_ok()
+            {
+                result = contents.try_get().map(drop);
+            }
+        });
+        result
+    }
+
+    /// Returns whether the contents of every file in this directory and its subdirectories are in
+    /// memory (see [FileContents::is_loaded]).
+    pub fn is_loaded(&self) -> bool {
+        let mut loaded = true;
+        self.visit(&mut PathBuf::new(), &mut |_, entry| {
+            if let RawEntry::File(contents, _) = entry {
+                loaded &= contents.is_loaded();
+            }
+        });
+        loaded
+    }
+
+    /// Returns the path and target of the symlinks in this directory and its subdirectories.
+    /// Paths are relative to this directory.
+    pub fn symlinks_recursive(&self) -> Vec<(PathBuf, &Path)> {
+        let mut out = vec![];
+        self.visit(&mut PathBuf::new(), &mut |path, entry| {
+            if let RawEntry::Symlink(target) = entry {
+                out.push((path.to_path_buf(), target.as_path()));
+            }
+        });
+        out
+    }
+
+    /// Calls `f` with the path and value of every non-directory entry and every empty directory
+    /// under this directory.
+    fn visit<'s, F: FnMut(&Path, &'s RawEntry)>(&'s self, path: &mut PathBuf, f: &mut F) {
+        for (name, entry) in self.0.iter() {
+            path.push(name);
+            match entry {
+                RawEntry::Dir(dir) if !dir.0.is_empty() => dir.visit(path, f),
+                entry => f(path, entry),
+            }
+            path.pop();
+        }
+    }
+
+    /// Returns the directory at `segments` (which must not contain symlinks), if it exists.
+    fn dir_at(&self, segments: &[OsString]) -> Option<&RawDir> {
+        segments
+            .iter()
+            .try_fold(self, |dir, name| dir.0.get(name)?.dir())
+    }
+
+    /// Resolves `path` into the sequence of names of entries that must be traversed to reach it,
+    /// following any symlinks that are in the directory tree (including a symlink at the end of
+    /// `path`, if `follow_last` is true). `..` is resolved after symlinks, so it refers to the
+    /// parent of the directory a symlink points to.
+    ///
+    /// Also returns whether the path could refer to a file (i.e. it did not end with `.` or `..`).
+    fn resolve(&self, path: &Path, follow_last: bool) -> Result<(Vec<OsString>, bool), Resolve> {
+        let mut components = VecDeque::new();
+        for component in path.components() {
+            components.push_back(match component {
+                Component::CurDir => None,
+                Component::Normal(name) => Some(name.to_os_string()),
+                Component::ParentDir => Some("..".into()),
+                Component::Prefix(_) | Component::RootDir => return Err(Resolve::AbsolutePath),
+            });
+        }
+        let mut segments: Vec<OsString> = vec![];
+        // Whether the most-recently-processed entry can be a file.
+        let mut last_can_be_file = true;
+        let mut hops = 0;
+        // `None` represents `.`.
+        while let Some(component) = components.pop_front() {
+            let Some(name) = component else {
+                last_can_be_file = false;
+                continue;
+            };
+            if name == ".." {
+                if segments.pop().is_none() {
+                    return Err(Resolve::OutsideDir);
+                }
+                last_can_be_file = false;
+                continue;
+            }
+            let follow = follow_last || !components.is_empty();
+            let target = self
+                .dir_at(&segments)
+                .and_then(|dir| dir.0.get(&name)?.symlink())
+                .filter(|_| follow);
+            let Some(target) = target else {
+                segments.push(name);
+                last_can_be_file = true;
+                continue;
+            };
+            hops += 1;
+            if hops > MAX_SYMLINK_HOPS {
+                return Err(Resolve::SymlinkLoop);
+            }
+            for component in target.components().rev() {
+                components.push_front(match component {
+                    Component::CurDir => None,
+                    Component::Normal(name) => Some(name.to_os_string()),
+                    Component::ParentDir => Some("..".into()),
+                    Component::Prefix(_) | Component::RootDir => {
+                        return Err(Resolve::OutsideDir);
+                    }
+                });
+            }
+        }
+        Ok((segments, last_can_be_file))
+    }
+
+    /// Gets the contents of a file at the given path. The file must
+    /// exist. On success, returns a reference to file's contents.
+    ///
+    /// `path` must be a relative path. Symlinks within this directory
+    /// are followed (including a symlink at the end of `path`), and
+    /// `..` refers to the parent of the directory a symlink points to,
+    /// as it would on a real file system. Symlinks that point outside
+    /// this directory (including absolute symlinks) cannot be followed.
+    ///
+    /// If the file is lazily loaded and cannot be read, returns
+    /// [GetFileError::Unreadable].
+    pub fn get_file<P: AsRef<Path>>(&self, path: P) -> Result<&[u8], GetFileError> {
+        let (contents, _) = self.get_file_entry(path.as_ref())?;
+        contents
+            .try_get()
+            .map_err(|error| GetFileError::Unreadable(error.kind()))
+    }
+
+    /// Returns the metadata of the file at `path`, which is resolved in the same way as
+    /// [RawDir::get_file].
+    pub fn get_file_metadata<P: AsRef<Path>>(&self, path: P) -> Result<FileMetadata, GetFileError> {
+        self.get_file_entry(path.as_ref())
+            .map(|(_, &metadata)| metadata)
+    }
+
+    /// Implementation of [RawDir::get_file] and [RawDir::get_file_metadata].
+    fn get_file_entry(&self, path: &Path) -> Result<(&FileContents, &FileMetadata), GetFileError> {
+        let (mut segments, last_can_be_file) = self.resolve(path, true)?;
+        if !last_can_be_file {
+            return Err(GetFileError::Directory);
+        }
+        let file_name = match segments.pop() {
+            None => return Err(GetFileError::DoesNotExist),
+            Some(empty) if empty.is_empty() => return Err(GetFileError::DoesNotExist),
+            Some(name) => name,
+        };
+
+        let mut cur_dir = self;
+        for component in segments {
+            match cur_dir
+                .0
+                .get(&component)
+                .ok_or(GetFileError::DoesNotExist)?
+            {
+                RawEntry::Dir(rd) => cur_dir = rd,
+                // resolve() follows every symlink, so this is reached only for files.
+                _ => return Err(GetFileError::UnderFile),
+            }
+        }
+        match cur_dir
+            .0
+            .get(&file_name)
+            .ok_or(GetFileError::DoesNotExist)?
+        {
+            RawEntry::File(contents, metadata) => Ok((contents, metadata)),
+            _ => Err(GetFileError::Directory),
+        }
+    }
+
+    /// Creates a new file at the given path. The file must not already exist.
+    ///
+    /// `path` must be a relative path. Symlinks to directories within this directory are followed
+    /// in the same way as [RawDir::get_file], but if `path` itself names a symlink then the file
+    /// already exists.
+    ///
+    /// The file is given the default [FileMetadata] (see [RawDir::set_file_with_metadata]).
+    pub fn set_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        contents: Vec<u8>,
+    ) -> Result<(), SetFileError> {
+        self.set_file_with_metadata(path, contents, FileMetadata::default())
+    }
+
+    /// Like [RawDir::set_file], but gives the new file the specified metadata.
+    pub fn set_file_with_metadata<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        contents: Vec<u8>,
+        metadata: FileMetadata,
+    ) -> Result<(), SetFileError> {
+        let (mut segments, last_can_be_file) = self.resolve(path.as_ref(), false)?;
+        if !last_can_be_file {
+            return Err(SetFileError::Directory);
+        }
+        let filename = match segments.pop() {
+            None => return Err(SetFileError::EmptyFileName),
+            Some(empty) if empty.is_empty() => return Err(SetFileError::EmptyFileName),
+            Some(name) => name,
+        };
+
+        // Traverse through the directory tree to find the file entry.
+        let mut cur_dir = self;
+        for dir_name in segments {
+            let RawDir(map) = cur_dir;
+            let new_dir = map
+                .entry(dir_name)
+                .or_insert_with(|| RawEntry::Dir(RawDir::default()));
+            let RawEntry::Dir(new_dir) = new_dir else {
+                return Err(SetFileError::UnderFile);
+            };
+            cur_dir = new_dir;
+        }
+        let btree_map::Entry::Vacant(entry) = cur_dir.0.entry(filename) else {
+            return Err(SetFileError::AlreadyExists);
+        };
+        entry.insert(RawEntry::File(contents.into(), metadata));
+        Ok(())
+    }
+
+    /// Materializes the [RawDir] to the file system. Symlinks are recreated as symlinks (which is
+    /// only supported on Unix). On Unix, files are given the permissions in their [FileMetadata].
+    ///
+    /// `path` is a path to an empty or non-existent directory noting
+    /// where the file system should be materialized to. Empty directories are created.
+    /// Lazily-loaded files that cannot be read are reported as errors.
+    pub fn materialize<P: AsRef<Path>>(&self, base_path: P) -> std::io::Result<()> {
+        let base_path = base_path.as_ref();
+        let create_parent = |path: &Path| match path.parent() {
+            Some(parent) => std::fs::create_dir_all(base_path.join(parent)),
+            None => std::fs::create_dir_all(base_path),
+        };
+        let mut files = vec![];
+        let mut empty_dirs = vec![];
+        self.visit(&mut PathBuf::new(), &mut |path, entry| match entry {
+            RawEntry::File(contents, metadata) => {
+                files.push((path.to_path_buf(), contents, metadata))
+            }
+            RawEntry::Dir(_) => empty_dirs.push(path.to_path_buf()),
+            RawEntry::Symlink(_) => {}
+        });
+        for dir_path in empty_dirs {
+            std::fs::create_dir_all(base_path.join(dir_path))?;
+        }
+        for (file_path, contents, _metadata) in files {
+            create_parent(&file_path)?;
+            let file_path = base_path.join(file_path);
+            contents.write_to(&mut std::fs::File::create(&file_path)?)?;
+            #[cfg(unix)]
+            std::fs::set_permissions(
+                file_path,
+                std::os::unix::fs::PermissionsExt::from_mode(_metadata.mode),
+            )?;
+        }
+        for (link_path, target) in self.symlinks_recursive() {
+            create_parent(&link_path)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(target, base_path.join(link_path))?;
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot materialize symlink to {}", target.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Error type returned by [RawDir::resolve].
+enum Resolve {
+    AbsolutePath,
+    OutsideDir,
+    SymlinkLoop,
+}
+
+// RawDir is serialized as a map from entry name to entry. Each entry is one of `{"dir": {...}}`,
+// `{"file": "contents"}` (for UTF-8 files), `{"binary_file": [bytes...]}` (for other files), or
+// `{"symlink": "target"}`. Files with non-default metadata also have a `"mode"` key.
+impl Serialize for RawDir {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, entry) in &self.0 {
+            let Some(name) = name.to_str() else {
+                return Err(S::Error::custom(format!("non-UTF-8 file name {name:?}")));
+            };
+            map.serialize_entry(name, entry)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for RawDir {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = BTreeMap::<String, RawEntry>::deserialize(deserializer)?;
+        let invalid = |name: &String| matches!(&**name, "" | "." | "..") || name.contains('/');
+        if entries.keys().any(invalid) {
+            return Err(D::Error::custom("invalid file name in RawDir"));
+        }
+        Ok(RawDir(
+            entries.into_iter().map(|(n, e)| (n.into(), e)).collect(),
+        ))
+    }
+}
+
+impl Serialize for RawEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match self {
+            RawEntry::Dir(dir) => map.serialize_entry("dir", dir)?,
+            RawEntry::File(contents, metadata) => {
+                let contents = contents.try_get().map_err(S::Error::custom)?;
+                match str::from_utf8(contents) {
+                    Ok(text) => map.serialize_entry("file", text)?,
+                    Err(_) => map.serialize_entry("binary_file", contents)?,
+                }
+                if *metadata != FileMetadata::default() {
+                    map.serialize_entry("mode", &metadata.mode)?;
+                }
+            }
+            RawEntry::Symlink(target) => match target.to_str() {
+                Some(target) => map.serialize_entry("symlink", target)?,
+                None => {
+                    return Err(S::Error::custom(format!(
+                        "non-UTF-8 symlink target {target:?}"
+                    )));
+                }
+            },
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for RawEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Serialized {
+            dir: Option<RawDir>,
+            file: Option<String>,
+            binary_file: Option<Vec<u8>>,
+            symlink: Option<String>,
+            mode: Option<u32>,
+        }
+        let Serialized {
+            dir,
+            file,
+            binary_file,
+            symlink,
+            mode,
+        } = Serialized::deserialize(deserializer)?;
+        let metadata = mode.map(FileMetadata::normalized);
+        Ok(match (dir, file, binary_file, symlink, metadata) {
+            (Some(dir), None, None, None, None) => RawEntry::Dir(dir),
+            (None, Some(text), None, None, metadata) => {
+                RawEntry::File(text.into_bytes().into(), metadata.unwrap_or_default())
+            }
+            (None, None, Some(contents), None, metadata) => {
+                RawEntry::File(contents.into(), metadata.unwrap_or_default())
+            }
+            (None, None, None, Some(target), None) => RawEntry::Symlink(target.into()),
+            _ => return Err(D::Error::custom("invalid RawEntry")),
+        })
+    }
+}
+
+/// Error type returned by [RawDir::set_file].
+#[derive(Debug, Eq, Hash, PartialEq, thiserror::Error)]
+pub enum SetFileError {
+    #[error("tried to set file at absolute path")]
+    AbsolutePath,
+    #[error("file already exists")]
+    AlreadyExists,
+    #[error("tried to set file at directory path")]
+    Directory,
+    #[error("empty file name")]
+    EmptyFileName,
+    #[error("tried to write file outside this directory")]
+    OutsideDir,
+    #[error("too many levels of symbolic links")]
+    SymlinkLoop,
+    #[error("tried to set a file that is under another file")]
+    UnderFile,
+}
+
+impl From<Resolve> for SetFileError {
+    fn from(error: Resolve) -> SetFileError {
+        match error {
+            Resolve::AbsolutePath => SetFileError::AbsolutePath,
+            Resolve::OutsideDir => SetFileError::OutsideDir,
+            Resolve::SymlinkLoop => SetFileError::SymlinkLoop,
+        }
+    }
+}
+
+/// Error type returned by [RawDir::get_file].
+#[derive(Debug, Eq, Hash, PartialEq, thiserror::Error)]
+pub enum GetFileError {
+    #[error("tried to get file at absolute path")]
+    AbsolutePath,
+    #[error("tried to get file at directory path")]
+    Directory,
+    #[error("tried to get a file outside this directory")]
+    OutsideDir,
+    #[error("too many levels of symbolic links")]
+    SymlinkLoop,
+    #[error("tried to get a file that is under another file")]
+    UnderFile,
+    #[error("tried to get a file that does not exist")]
+    DoesNotExist,
+    /// The file is lazily loaded (see [PopulateOptions::lazy]), and reading it failed.
+    #[error("failed to read file: {0}")]
+    Unreadable(io::ErrorKind),
+}
+
+impl From<Resolve> for GetFileError {
+    fn from(error: Resolve) -> GetFileError {
+        match error {
+            Resolve::AbsolutePath => GetFileError::AbsolutePath,
+            Resolve::OutsideDir => GetFileError::OutsideDir,
+            Resolve::SymlinkLoop => GetFileError::SymlinkLoop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_recursive() {
+        #[rustfmt::skip]
+        let dir = RawDir([
+            ("dir1".into(), RawEntry::Dir(RawDir([
+                ("dir2".into(), RawEntry::Dir(RawDir([
+                    ("file2.txt".into(), RawEntry::File(b"B".into(), FileMetadata::default())),
+                ].into_iter().collect()))),
+                ("file3.txt".into(), RawEntry::File(b"C".into(), FileMetadata::default())),
+            ].into_iter().collect()))),
+            ("file1.txt".into(), RawEntry::File(b"A".into(), FileMetadata::default())),
+        ].into_iter().collect());
+        // TODO: This comparison is sensitive to the order that files_recursive outputs its files,
+        // which is not specified. We should either specify files_recursive's iteration order or
+        // make this test insensitive to order.
+        assert_eq!(
+            dir.files_recursive(),
+            [
+                (PathBuf::from("dir1/dir2/file2.txt"), b"B".as_slice()),
+                (PathBuf::from("dir1/file3.txt"), b"C".as_slice()),
+                (PathBuf::from("file1.txt"), b"A".as_slice())
+            ]
+        );
+    }
+
+    #[test]
+    fn serde() {
+        let mut dir = RawDir::default();
+        dir.set_file("a/text.txt", b"hello".into()).unwrap();
+        dir.set_file("binary", vec![0xff, 0]).unwrap();
+        dir.set_file_with_metadata("run.sh", b"make".into(), FileMetadata::EXECUTABLE)
+            .unwrap();
+        let json = serde_json::to_value(&dir).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "a": {"dir": {"text.txt": {"file": "hello"}}},
+                "binary": {"binary_file": [255, 0]},
+                "run.sh": {"file": "make", "mode": 0o755},
+            })
+        );
+        assert_eq!(serde_json::from_value::<RawDir>(json).unwrap(), dir);
+        assert!(
+            serde_json::from_value::<RawDir>(serde_json::json!({"../x": {"file": ""}})).is_err()
+        );
+        // Modes are normalized when deserializing.
+        let json = serde_json::json!({"run.sh": {"file": "make", "mode": 0o700}});
+        assert_eq!(
+            serde_json::from_value::<RawDir>(json)
+                .unwrap()
+                .get_file_metadata("run.sh"),
+            Ok(FileMetadata::EXECUTABLE)
+        );
+    }
+
+    #[test]
+    fn set_file() {
+        let mut root = RawDir::default();
+        assert!(root.set_file("file1.txt", b"A".into()).is_ok());
+        assert!(root.set_file("dir1/dir2/file2.txt", b"B".into()).is_ok());
+        assert!(root.set_file("dir1/file3.txt", b"C".into()).is_ok());
+        assert_eq!(
+            root.set_file("/etc/passwd", b"D".into()),
+            Err(SetFileError::AbsolutePath)
+        );
+        assert_eq!(
+            root.set_file("dir1/file3.txt", b"E".into()),
+            Err(SetFileError::AlreadyExists)
+        );
+        assert_eq!(
+            root.set_file(".", b"F".into()),
+            Err(SetFileError::Directory)
+        );
+        assert_eq!(
+            root.set_file("dir1/dir2/..", b"G".into()),
+            Err(SetFileError::Directory)
+        );
+        assert_eq!(
+            root.set_file("", b"H".into()),
+            Err(SetFileError::EmptyFileName)
+        );
+        assert_eq!(
+            root.set_file("../", b"I".into()),
+            Err(SetFileError::OutsideDir)
+        );
+        assert_eq!(
+            root.set_file("dir1/../../", b"J".into()),
+            Err(SetFileError::OutsideDir)
+        );
+        assert_eq!(
+            root.set_file("file1.txt/file4.txt", b"K".into()),
+            Err(SetFileError::UnderFile)
+        );
+        #[rustfmt::skip]
+        assert_eq!(root, RawDir([
+            ("dir1".into(), RawEntry::Dir(RawDir([
+                ("dir2".into(), RawEntry::Dir(RawDir([
+                    ("file2.txt".into(), RawEntry::File(b"B".into(), FileMetadata::default())),
+                ].into_iter().collect()))),
+                ("file3.txt".into(), RawEntry::File(b"C".into(), FileMetadata::default())),
+            ].into_iter().collect()))),
+            ("file1.txt".into(), RawEntry::File(b"A".into(), FileMetadata::default())),
+        ].into_iter().collect()));
+    }
+
+    #[test]
+    fn symlinks() {
+        let mut root = RawDir::default();
+        root.set_file("include/real/a.h", b"A".into()).unwrap();
+        root.set_file("src/main.c", b"M".into()).unwrap();
+        let link = |dir: &mut RawDir, name: &str, target: &str| {
+            dir.0.insert(name.into(), RawEntry::Symlink(target.into()));
+        };
+        link(&mut root, "alias", "include/real");
+        link(&mut root, "a.h", "alias/a.h");
+        link(&mut root, "abs", "/usr/include");
+        link(&mut root, "loop", "loop");
+        link(&mut root, "escape", "..");
+        assert_eq!(root.get_file("alias/a.h"), Ok(b"A".as_slice()));
+        assert_eq!(root.get_file("a.h"), Ok(b"A".as_slice()));
+        // `..` is resolved relative to the symlink's target.
+        assert_eq!(root.get_file("alias/../real/a.h"), Ok(b"A".as_slice()));
+        assert_eq!(root.get_file("alias/../../src/main.c"), Ok(b"M".as_slice()));
+        assert_eq!(root.get_file("alias"), Err(GetFileError::Directory));
+        assert_eq!(root.get_file("abs/stdio.h"), Err(GetFileError::OutsideDir));
+        assert_eq!(root.get_file("escape/x"), Err(GetFileError::OutsideDir));
+        assert_eq!(root.get_file("loop"), Err(GetFileError::SymlinkLoop));
+
+        assert!(root.set_file("alias/b.h", b"B".into()).is_ok());
+        assert_eq!(root.get_file("include/real/b.h"), Ok(b"B".as_slice()));
+        assert_eq!(
+            root.set_file("a.h", b"C".into()),
+            Err(SetFileError::AlreadyExists)
+        );
+        assert_eq!(
+            root.set_file("escape/x", b"D".into()),
+            Err(SetFileError::OutsideDir)
+        );
+
+        assert_eq!(
+            root.files_recursive(),
+            [
+                (PathBuf::from("include/real/a.h"), b"A".as_slice()),
+                (PathBuf::from("include/real/b.h"), b"B".as_slice()),
+                (PathBuf::from("src/main.c"), b"M".as_slice()),
+            ]
+        );
+        assert_eq!(root.symlinks_recursive().len(), 5);
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(
+            json["alias"],
+            serde_json::json!({"symlink": "include/real"})
+        );
+        assert_eq!(serde_json::from_value::<RawDir>(json).unwrap(), root);
+    }
+
+    #[cfg(all(unix, not(miri)))]
+    #[test]
+    fn populate_symlinks() {
+        use std::fs::read_dir;
+        use std::os::unix::fs::symlink;
+        let source = tempfile::tempdir().unwrap();
+        let mut root = RawDir::default();
+        root.set_file("include/a.h", b"A".into()).unwrap();
+        root.0
+            .insert("alias".into(), RawEntry::Symlink("include".into()));
+        root.materialize(source.path()).unwrap();
+        assert_eq!(
+            std::fs::read_link(source.path().join("alias")).unwrap(),
+            Path::new("include")
+        );
+
+        let options = |symlinks| PopulateOptions {
+            symlinks,
+            ..Default::default()
+        };
+        let read = || read_dir(source.path()).unwrap();
+        let (preserved, dirs, files) = RawDir::populate_from(read()).unwrap();
+        assert_eq!((&preserved, dirs, files), (&root, 1, 1));
+        let followed = RawDir::populate_with(read(), &options(SymlinkPolicy::Follow)).unwrap();
+        assert_eq!((followed.directories, followed.files), (2, 2));
+        let followed = followed.dir;
+        assert_eq!(followed.get_file("alias/a.h"), Ok(b"A".as_slice()));
+        assert!(followed.symlinks_recursive().is_empty());
+        assert!(RawDir::populate_with(read(), &options(SymlinkPolicy::Reject)).is_err());
+
+        symlink("..", source.path().join("include/up")).unwrap();
+        assert!(RawDir::populate_with(read(), &options(SymlinkPolicy::Follow)).is_err());
+    }
+
+    #[cfg(all(unix, not(miri)))]
+    #[test]
+    fn file_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = tempfile::tempdir().unwrap();
+        let mut root = RawDir::default();
+        let configure = RawEntry::File(b"#!/bin/sh".into(), FileMetadata::normalized(0o700));
+        root.0.insert("configure".into(), configure);
+        root.set_file("src/main.c", b"".into()).unwrap();
+        root.materialize(source.path()).unwrap();
+        let mode = |path| {
+            let metadata = std::fs::metadata(source.path().join(path)).unwrap();
+            metadata.permissions().mode() & 0o7777
+        };
+        assert_eq!(mode("configure"), 0o755);
+        assert_eq!(mode("src/main.c"), 0o644);
+
+        // Loading the directory normalizes the modes.
+        let (loaded, _, _) =
+            RawDir::populate_from(std::fs::read_dir(source.path()).unwrap()).unwrap();
+        assert_eq!(
+            loaded.get_file_metadata("configure"),
+            Ok(FileMetadata::EXECUTABLE)
+        );
+        assert_eq!(
+            loaded.get_file_metadata("src/main.c"),
+            Ok(FileMetadata::default())
+        );
+        assert!(
+            loaded
+                .get_file_metadata("configure")
+                .unwrap()
+                .is_executable()
+        );
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn populate_filter() {
+        use std::fs::read_dir;
+        let source = tempfile::tempdir().unwrap();
+        let mut root = RawDir::default();
+        root.set_file("src/main.c", b"int main() {}".into())
+            .unwrap();
+        root.set_file("src/data.bin", vec![0; 100]).unwrap();
+        root.set_file("build/main.o", b"".into()).unwrap();
+        root.materialize(source.path()).unwrap();
+        let options = PopulateOptions {
+            filter: Some(Arc::new(|path, _| path != Path::new("build"))),
+            max_file_size: Some(50),
+            ..Default::default()
+        };
+        let populated = RawDir::populate_with(read_dir(source.path()).unwrap(), &options).unwrap();
+        assert_eq!((populated.directories, populated.files), (1, 1));
+        let files = Vec::from_iter(populated.dir.files_recursive().into_iter().map(|(p, _)| p));
+        assert_eq!(files, [PathBuf::from("src/main.c")]);
+        assert_eq!(
+            populated.skipped,
+            [
+                (PathBuf::from("build"), SkipReason::Filtered),
+                (PathBuf::from("src/data.bin"), SkipReason::TooLarge(100)),
+            ]
+        );
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn populate_lazy() {
+        use std::fs::read_dir;
+        let source = tempfile::tempdir().unwrap();
+        let mut root = RawDir::default();
+        root.set_file("src/main.c", b"int main() {}".into())
+            .unwrap();
+        root.set_file("src/util.c", b"".into()).unwrap();
+        root.materialize(source.path()).unwrap();
+        let options = PopulateOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        let populated = RawDir::populate_with(read_dir(source.path()).unwrap(), &options).unwrap();
+        let lazy = populated.dir;
+        let contents = |dir: &RawDir| {
+            dir.get_file_entry(Path::new("src/main.c"))
+                .unwrap()
+                .0
+                .clone()
+        };
+        assert!(!contents(&lazy).is_loaded());
+        // Hashing and materializing stream the contents rather than loading them.
+        assert_eq!(lazy.content_hash(), root.content_hash());
+        let copy = tempfile::tempdir().unwrap();
+        lazy.materialize(copy.path()).unwrap();
+        assert_eq!(
+            std::fs::read(copy.path().join("src/main.c")).unwrap(),
+            b"int main() {}"
+        );
+        assert!(!lazy.is_loaded());
+        // Clones share the contents, so loading them in one loads them in the other.
+        let clone = lazy.clone();
+        assert_eq!(
+            clone.get_file("src/main.c"),
+            Ok(b"int main() {}".as_slice())
+        );
+        assert!(contents(&lazy).is_loaded());
+        assert_eq!(lazy, root);
+
+        // Files that can no longer be read are reported as errors.
+        let lazy = RawDir::populate_with(read_dir(source.path()).unwrap(), &options)
+            .unwrap()
+            .dir;
+        std::fs::write(
+            source.path().join("src/main.c"),
+            b"int main() { return 0; }",
+        )
+        .unwrap();
+        std::fs::remove_file(source.path().join("src/util.c")).unwrap();
+        assert_eq!(
+            lazy.get_file("src/main.c"),
+            Err(GetFileError::Unreadable(io::ErrorKind::InvalidData))
+        );
+        assert_eq!(
+            lazy.get_file("src/util.c"),
+            Err(GetFileError::Unreadable(io::ErrorKind::NotFound))
+        );
+        assert!(lazy.load_contents().is_err());
+        assert!(lazy.materialize(source.path().join("out")).is_err());
+        assert!(lazy.try_files_recursive().is_err());
+        assert!(lazy.try_content_hash().is_err());
+        assert!(lazy.try_diff(&root).is_err());
+        assert!(root.try_diff(&lazy).is_err());
+        // Comparing a lazy directory with its own clone does not read it.
+        assert!(lazy.try_diff(&lazy.clone()).unwrap().changes.is_empty());
+    }
+}
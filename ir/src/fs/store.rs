@@ -0,0 +1,104 @@
+//! A content-addressed store of [RawDir]s on the file system.
+
+use super::{ContentHash, PopulateOptions, RawDir};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, rename};
+use std::io;
+use std::path::PathBuf;
+
+/// A directory containing materialized [RawDir]s (objects), each in a subdirectory named by its
+/// [ContentHash]. Storing a tree that is already present writes nothing, so a tree that is shared
+/// between several saved IRs is only stored once.
+#[derive(Clone, Debug)]
+pub struct ObjectStore {
+    dir: PathBuf,
+}
+
+impl ObjectStore {
+    /// Returns the store in `dir`. The directory is created when the first object is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> ObjectStore {
+        ObjectStore { dir: dir.into() }
+    }
+
+    /// Returns the path at which the object with hash `hash` is (or would be) stored.
+    pub fn path(&self, hash: ContentHash) -> PathBuf {
+        self.dir.join(hash.to_string())
+    }
+
+    /// Stores `dir` unless an object with the same hash is already present, and returns its hash.
+    /// Lazily-loaded files that cannot be read are reported as errors.
+    pub fn store(&self, dir: &RawDir) -> io::Result<ContentHash> {
+        let hash = dir.try_content_hash()?;
+        self.insert(hash, dir)?;
+        Ok(hash)
+    }
+
+    /// Stores `dir` under `hash`, which must be its [content hash](RawDir::content_hash), unless
+    /// an object with that hash is already present. This lets callers that already know the hash
+    /// avoid computing it again.
+    pub fn insert(&self, hash: ContentHash, dir: &RawDir) -> io::Result<()> {
+        let path = self.path(hash);
+        if !path.exists() {
+            // Materialize into a temporary directory first, so that an interrupted write does not
+            // leave an incomplete object behind.
+            let partial = self.dir.join(format!("{hash}.partial"));
+            if partial.exists() {
+                remove_dir_all(&partial)?;
+            }
+            create_dir_all(&partial)?;
+            dir.materialize(&partial)?;
+            rename(&partial, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the object with hash `hash`. Its files are loaded lazily (see
+    /// [PopulateOptions::lazy]), so the object must not be modified while the returned [RawDir] is
+    /// in use.
+    pub fn load(&self, hash: ContentHash) -> io::Result<RawDir> {
+        let options = PopulateOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        Ok(RawDir::populate_with(read_dir(self.path(hash))?, &options)?.dir)
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+    use crate::fs::RawEntry;
+
+    #[test]
+    fn store_and_load() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = ObjectStore::new(tempdir.path().join("objects"));
+        let mut dir = RawDir::default();
+        dir.set_file("src/main.c", b"int main;\n".into()).unwrap();
+        dir.0
+            .insert("empty".into(), RawEntry::Dir(RawDir::default()));
+        let hash = store.store(&dir).unwrap();
+        assert_eq!(hash, dir.content_hash());
+        assert_eq!(store.store(&dir.clone()).unwrap(), hash);
+        assert_eq!(read_dir(tempdir.path().join("objects")).unwrap().count(), 1);
+        let loaded = store.load(hash).unwrap();
+        assert_eq!(loaded.content_hash(), hash);
+        assert_eq!(loaded.get_file("src/main.c"), Ok(b"int main;\n".as_slice()));
+        assert!(store.load(RawDir::default().content_hash()).is_err());
+    }
+
+    /// Files with modes that are not already normalized (e.g. from deserialized IRs) keep their
+    /// content hash when stored and loaded again.
+    #[cfg(unix)]
+    #[test]
+    fn modes() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let store = ObjectStore::new(tempdir.path());
+        let dir: RawDir = serde_json::from_value(serde_json::json!({
+            "configure": {"file": "#!/bin/sh", "mode": 0o700},
+            "secret": {"file": "", "mode": 0o600},
+        }))
+        .unwrap();
+        let hash = store.store(&dir).unwrap();
+        assert_eq!(store.load(hash).unwrap().content_hash(), hash);
+    }
+}
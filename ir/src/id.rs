@@ -1,4 +1,7 @@
-use std::fmt::{self, Display, Formatter};
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 use std::num::NonZeroU64;
 use std::process::abort;
 use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
@@ -32,13 +35,21 @@ impl Id {
     /// # }
     /// ```
     pub fn new_array<const LEN: usize>() -> [Id; LEN] {
-        // The highest ID allocated so far. Each new_array() call starts
-        // allocating IDs at HIGHEST_ID + 1.
-        static HIGHEST_ID: AtomicU64 = AtomicU64::new(0);
         new_array_testable(&HIGHEST_ID)
     }
+
+    /// Returns the ID with the given number, and guarantees that `Id::new` and `Id::new_array`
+    /// will not return that ID in the future. Used to restore IDs when loading a saved
+    /// [HarvestIR](crate::HarvestIR).
+    pub(crate) fn reserve(num: NonZeroU64) -> Id {
+        HIGHEST_ID.fetch_max(num.get(), Relaxed);
+        Id(num)
+    }
 }
 
+// The highest ID allocated so far. Each new_array() call starts allocating IDs at HIGHEST_ID + 1.
+static HIGHEST_ID: AtomicU64 = AtomicU64::new(0);
+
 impl Display for Id {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "Id({})", self.0)
@@ -51,6 +62,94 @@ impl From<Id> for u64 {
     }
 }
 
+impl From<Id> for NonZeroU64 {
+    fn from(value: Id) -> NonZeroU64 {
+        value.0
+    }
+}
+
+/// An [Id] that is known to refer to a representation of type `R`. Typed IDs are obtained from
+/// [HarvestIR::typed_id](crate::HarvestIR::typed_id) (which checks the representation's type) or
+/// [Edit::add_typed](crate::Edit::add_typed), and are used with
+/// [HarvestIR::get](crate::HarvestIR::get) and [Edit::write_typed](crate::Edit::write_typed).
+///
+/// A `TypedId` does not keep its representation alive: the representation may later be removed,
+/// in which case looking it up returns `None`.
+pub struct TypedId<R> {
+    id: Id,
+    // fn() -> R is used so that TypedId is Send and Sync regardless of R.
+    _type: PhantomData<fn() -> R>,
+}
+
+impl<R> TypedId<R> {
+    /// Wraps `id` without checking the type of the representation it refers to.
+    pub(crate) fn new_unchecked(id: Id) -> TypedId<R> {
+        TypedId {
+            id,
+            _type: PhantomData,
+        }
+    }
+
+    /// Returns the untyped ID.
+    pub fn id(self) -> Id {
+        self.id
+    }
+}
+
+impl<R> From<TypedId<R>> for Id {
+    fn from(value: TypedId<R>) -> Id {
+        value.id
+    }
+}
+
+// These traits are implemented manually because #[derive] would require R to implement them as
+// well.
+impl<R> Clone for TypedId<R> {
+    fn clone(&self) -> TypedId<R> {
+        *self
+    }
+}
+
+impl<R> Copy for TypedId<R> {}
+
+impl<R> Debug for TypedId<R> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "TypedId<{}>({})", crate::short_type_name::<R>(), self.id)
+    }
+}
+
+impl<R> Display for TypedId<R> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.id, f)
+    }
+}
+
+impl<R> PartialEq for TypedId<R> {
+    fn eq(&self, other: &TypedId<R>) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<R> Eq for TypedId<R> {}
+
+impl<R> Hash for TypedId<R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<R> PartialOrd for TypedId<R> {
+    fn partial_cmp(&self, other: &TypedId<R>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for TypedId<R> {
+    fn cmp(&self, other: &TypedId<R>) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
 // `Id::new_array`, but with an injected AtomicU64. This allows `tests::new` to
 // use its own AtomicU64, which prevents other tests that are run in parallel
 // from interfering with it.
@@ -125,8 +224,7 @@ mod tests {
             });
             let chunks = s.spawn(|| {
                 (0..10)
-                    .map(|_| new_array_testable::<CHUNK_SIZE>(highest_id))
-                    .flatten()
+                    .flat_map(|_| new_array_testable::<CHUNK_SIZE>(highest_id))
                     .collect()
             });
             let all_at_once = new_array_testable::<{ 10 * CHUNK_SIZE }>(highest_id).into();
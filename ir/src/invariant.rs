@@ -0,0 +1,87 @@
+//! Checks for properties that every version of a [HarvestIR] should satisfy.
+//!
+//! Invariants are registered with an [edit::Organizer](crate::edit::Organizer) using
+//! `add_invariant`, and checked with `check_invariants` (typically after every applied edit), so
+//! that a broken invariant can be traced back to the edit that broke it.
+
+use crate::{HarvestIR, Representation, short_type_name};
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+/// A property of a [HarvestIR] that should always hold.
+pub trait IrInvariant: Send + Sync + 'static {
+    /// This invariant's name. Used for diagnostics.
+    fn name(&self) -> String;
+
+    /// Checks whether `ir` satisfies this invariant, returning a description of the problem if it
+    /// does not.
+    fn check(&self, ir: &HarvestIR) -> Result<(), String>;
+}
+
+/// A failed [IrInvariant] check.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Violation {
+    /// The name of the invariant that was violated.
+    pub invariant: String,
+    /// The description of the problem returned by [IrInvariant::check].
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.invariant, self.message)
+    }
+}
+
+/// Invariant that the IR contains at most one (live) representation of type `R`.
+pub struct AtMostOne<R>(PhantomData<fn() -> R>);
+
+impl<R: Representation> AtMostOne<R> {
+    pub fn new() -> AtMostOne<R> {
+        AtMostOne(PhantomData)
+    }
+}
+
+impl<R: Representation> Default for AtMostOne<R> {
+    fn default() -> AtMostOne<R> {
+        AtMostOne::new()
+    }
+}
+
+impl<R: Representation> IrInvariant for AtMostOne<R> {
+    fn name(&self) -> String {
+        format!("at_most_one_{}", short_type_name::<R>())
+    }
+
+    fn check(&self, ir: &HarvestIR) -> Result<(), String> {
+        match ir.get_by_representation::<R>().count() {
+            0 | 1 => Ok(()),
+            count => Err(format!(
+                "found {count} {} representations",
+                short_type_name::<R>()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{EmptyRepresentation, IdRepresentation};
+
+    #[test]
+    fn at_most_one() {
+        let invariant = AtMostOne::<IdRepresentation>::new();
+        assert_eq!(invariant.name(), "at_most_one_IdRepresentation");
+        let mut ir = HarvestIR::default();
+        ir.add_representation(Box::new(EmptyRepresentation));
+        assert_eq!(invariant.check(&ir), Ok(()));
+        ir.add_representation(Box::new(IdRepresentation(1)));
+        assert_eq!(invariant.check(&ir), Ok(()));
+        ir.add_representation(Box::new(IdRepresentation(2)));
+        assert_eq!(
+            invariant.check(&ir),
+            Err("found 2 IdRepresentation representations".into())
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A run-independent identifier for a representation. Unlike an [Id](crate::Id), which depends on
+/// the order in which IDs were allocated, a stable key is derived from the name of the producer
+/// that wrote the representation and the stable keys of the representations it read (see
+/// [Edit::set_stable_producer](crate::Edit::set_stable_producer)). Two runs on the same input
+/// therefore assign the same keys to corresponding representations, which makes it possible to
+/// match representations up across runs.
+///
+/// A stable key identifies where a representation came from, not its contents: if a tool is
+/// nondeterministic, its outputs have the same keys across runs even if they differ. Displays as
+/// 32 lowercase hex digits.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StableKey([u8; 16]);
+
+impl StableKey {
+    /// Derives the key of the `index`th representation named `name` that `producer` wrote from
+    /// the representations with keys `inputs`. The order of `inputs` does not matter.
+    pub(crate) fn derive(
+        producer: &str,
+        inputs: impl IntoIterator<Item = StableKey>,
+        name: &str,
+        index: u64,
+    ) -> StableKey {
+        let mut inputs = Vec::from_iter(inputs);
+        inputs.sort_unstable();
+        let mut hasher = Sha256::new();
+        for field in [producer.as_bytes(), name.as_bytes()] {
+            hasher.update(u64::try_from(field.len()).unwrap().to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update(index.to_le_bytes());
+        hasher.update(u64::try_from(inputs.len()).unwrap().to_le_bytes());
+        inputs.iter().for_each(|input| hasher.update(input.0));
+        let hash: [u8; 32] = hasher.finalize().into();
+        StableKey(hash[..16].try_into().unwrap())
+    }
+}
+
+impl Debug for StableKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "StableKey({self})")
+    }
+}
+
+impl Display for StableKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl FromStr for StableKey {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<StableKey, ParseKeyError> {
+        if s.len() != 32 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseKeyError);
+        }
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| ParseKeyError)?;
+        }
+        Ok(StableKey(key))
+    }
+}
+
+/// Error returned when parsing a [StableKey] from a string that is not 32 hex digits.
+#[derive(Debug, Eq, Hash, PartialEq, Error)]
+#[error("invalid stable key")]
+pub struct ParseKeyError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive() {
+        let root = StableKey::derive("load_raw_source", [], "RawSource", 0);
+        let other = StableKey::derive("load_raw_source", [], "RawSource", 1);
+        assert_ne!(root, other);
+        assert_eq!(
+            StableKey::derive("tool", [root, other], "CargoPackage", 0),
+            StableKey::derive("tool", [other, root], "CargoPackage", 0)
+        );
+        assert_ne!(
+            StableKey::derive("tool", [root], "CargoPackage", 0),
+            StableKey::derive("tool", [other], "CargoPackage", 0)
+        );
+        // Fields are length-prefixed, so moving bytes between them changes the key.
+        assert_ne!(
+            StableKey::derive("ab", [], "c", 0),
+            StableKey::derive("a", [], "bc", 0)
+        );
+        // The key is stable.
+        assert_eq!(root.to_string(), "9f9b63231d305bc1fc10e60e6c1ed8e1");
+        assert_eq!(root.to_string().parse(), Ok(root));
+        assert_eq!("xyz".parse::<StableKey>(), Err(ParseKeyError));
+    }
+}
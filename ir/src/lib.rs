@@ -4,16 +4,21 @@
 pub mod edit;
 pub mod fs;
 mod id;
+pub mod invariant;
+mod key;
+pub mod serialize;
 
 pub use edit::Edit;
-pub use id::Id;
-use std::any::Any;
-use std::collections::BTreeMap;
+pub use id::{Id, TypedId};
+pub use key::{ParseKeyError, StableKey};
+use std::any::{Any, TypeId, type_name};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
 use std::fs::File;
 use std::io::Write as _;
 use std::path::Path;
 use std::sync::Arc;
+use thiserror::Error;
 
 /// Harvest Intermediate Representation
 ///
@@ -29,6 +34,46 @@ pub struct HarvestIR {
     // least gives us a stable ordering when iterating, e.g. to print
     // the IR.
     representations: BTreeMap<Id, Arc<dyn Representation>>,
+
+    // Index of the IDs of each type of representation, so that representations can be looked up
+    // by type without scanning the entire IR. Types with no representations are removed.
+    by_type: HashMap<TypeId, BTreeSet<Id>>,
+
+    // Representations that have been superseded by another representation. These are not
+    // returned by normal lookups, but are kept so the IR's history can be inspected.
+    superseded: BTreeMap<Id, Superseded>,
+
+    // Where each representation came from. Representations added directly (rather than through an
+    // [edit::Organizer]) have no provenance.
+    provenance: BTreeMap<Id, Arc<Provenance>>,
+
+    // The stable key of each representation that has one. Like provenance, keys are kept for
+    // superseded representations.
+    keys: BTreeMap<Id, StableKey>,
+
+    // Reverse index of [Representation::references]: maps each ID to the (live) representations
+    // that refer to it. IDs that nothing refers to are removed. The referenced IDs do not need to
+    // be present in `representations`; if they are not, the references are dangling.
+    referenced_by: BTreeMap<Id, BTreeSet<Id>>,
+}
+
+/// Records how a representation was produced.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Provenance {
+    /// The producer that wrote the representation (for tools, the tool run ID, such as
+    /// `try_cargo_build_001`), if it was recorded.
+    pub producer: Option<String>,
+    /// The IR version created by the edit that wrote the representation.
+    pub version: u64,
+    /// The representations the producer read to create this representation.
+    pub inputs: BTreeSet<Id>,
+}
+
+/// A representation that has been superseded by a newer representation.
+#[derive(Clone)]
+struct Superseded {
+    representation: Arc<dyn Representation>,
+    by: Id,
 }
 
 /// An abstract representation of a program
@@ -50,35 +95,275 @@ pub trait Representation: Any + Display + Send + Sync {
     fn materialize(&self, path: &Path) -> std::io::Result<()> {
         writeln!(File::create_new(path)?, "{self}")
     }
+
+    /// Returns the IDs of the representations this representation refers to (for example, the C
+    /// AST a Rust module was translated from). The IR indexes these references so they can be
+    /// queried in both directions (see [HarvestIR::references] and [HarvestIR::referenced_by]),
+    /// and [edit::Organizer::apply_edit] rejects edits that would leave a reference dangling.
+    ///
+    /// The returned IDs must not change while the representation is in the IR.
+    fn references(&self) -> Vec<Id> {
+        Vec::new()
+    }
+
+    /// Returns the file tree this representation consists of, if it is a file tree (such as a
+    /// source project). Diagnostics use this to show what changed between IR versions as a diff.
+    fn files(&self) -> Option<&fs::RawDir> {
+        None
+    }
+
+    /// Returns true if concurrent changes to this representation can be combined with
+    /// [Representation::merge]. [edit::Organizer::new_edit] allows several edits to write a
+    /// mergeable representation at the same time (but not alongside an edit that writes it
+    /// exclusively). Intended for collection-style representations, such as a list of diagnostics,
+    /// that several tools append to.
+    fn mergeable(&self) -> bool {
+        false
+    }
+
+    /// Merges two concurrent changes to a mergeable representation. `self` was written by an edit
+    /// that started from `base`; meanwhile, another edit replaced `base` with `current`. Returns
+    /// the representation containing both changes, or `None` if they conflict (in which case
+    /// [edit::Organizer::apply_edit] returns [edit::ApplyEditError::MergeConflict]).
+    ///
+    /// Only called if [Representation::mergeable] returns true. `base` and `current` are usually,
+    /// but not necessarily, of the same type as `self`.
+    fn merge(
+        &self,
+        base: &dyn Representation,
+        current: &dyn Representation,
+    ) -> Option<Box<dyn Representation>> {
+        let _ = (base, current);
+        None
+    }
 }
 
 impl HarvestIR {
     /// Adds a representation with a new ID and returns the new ID.
     pub fn add_representation(&mut self, representation: Box<dyn Representation>) -> Id {
         let id = Id::new();
-        self.representations.insert(id, representation.into());
+        self.insert(id, representation.into());
         id
     }
 
+    /// Stores `representation` under ID `id`, replacing any existing representation with that ID,
+    /// and updates the type and reference indexes. Returns the replaced representation, if there
+    /// was one.
+    fn insert(
+        &mut self,
+        id: Id,
+        representation: Arc<dyn Representation>,
+    ) -> Option<Arc<dyn Representation>> {
+        let new_type = (*representation).type_id();
+        let references = representation.references();
+        let old = self.representations.insert(id, representation);
+        if let Some(old) = &old {
+            self.remove_references(id, &**old);
+            if (**old).type_id() != new_type {
+                self.remove_from_index((**old).type_id(), id);
+            }
+        }
+        self.by_type.entry(new_type).or_default().insert(id);
+        for to in references {
+            self.referenced_by.entry(to).or_default().insert(id);
+        }
+        old
+    }
+
+    /// Removes the representation with ID `id`, returning it if it was present.
+    fn remove(&mut self, id: Id) -> Option<Arc<dyn Representation>> {
+        let old = self.representations.remove(&id)?;
+        self.remove_from_index((*old).type_id(), id);
+        self.remove_references(id, &*old);
+        Some(old)
+    }
+
+    /// Removes the references from `id` (whose representation is `representation`) from the
+    /// reference index.
+    fn remove_references(&mut self, id: Id, representation: &dyn Representation) {
+        for to in representation.references() {
+            if let Some(from) = self.referenced_by.get_mut(&to) {
+                from.remove(&id);
+                if from.is_empty() {
+                    self.referenced_by.remove(&to);
+                }
+            }
+        }
+    }
+
+    /// Marks the representation `old` as superseded by `new`, hiding it from lookups. Does
+    /// nothing if `old` is not present.
+    fn supersede(&mut self, old: Id, new: Id) {
+        if let Some(representation) = self.remove(old) {
+            let by = new;
+            self.superseded
+                .insert(old, Superseded { representation, by });
+        }
+    }
+
+    /// Removes `id` from the type index entry for `type_id`.
+    fn remove_from_index(&mut self, type_id: TypeId, id: Id) {
+        if let Some(ids) = self.by_type.get_mut(&type_id) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_type.remove(&type_id);
+            }
+        }
+    }
+
     /// Returns `true` if this `HarvestIR` contains a representation under ID `id`, `false`
     /// otherwise.
     pub fn contains_id(&self, id: Id) -> bool {
         self.representations.contains_key(&id)
     }
 
-    /// Returns all contained Representations of the given type.
+    /// Returns `id` as a [TypedId], if this IR contains a representation of type `R` under ID
+    /// `id`.
+    pub fn typed_id<R: Representation>(&self, id: Id) -> Option<TypedId<R>> {
+        let representation: &dyn Any = &**self.representations.get(&id)?;
+        representation.is::<R>().then(|| TypedId::new_unchecked(id))
+    }
+
+    /// Returns the representation with ID `id`, or `None` if it has been removed (or superseded),
+    /// or replaced with a representation of a different type using an untyped [Id].
+    pub fn get<R: Representation>(&self, id: TypedId<R>) -> Option<&R> {
+        let representation: &dyn Any = &**self.representations.get(&id.id())?;
+        representation.downcast_ref()
+    }
+
+    /// Returns all contained Representations of the given type, in ID order.
     pub fn get_by_representation<R: Representation>(&self) -> impl Iterator<Item = (Id, &R)> {
-        // TODO: Add a `TypeId -> Id` map to HarvestIR that allows us to look these up without
-        // scanning through all the other representations.
-        self.representations
-            .iter()
-            .filter_map(|(&i, r)| <dyn Any>::downcast_ref(&**r).map(|r| (i, r)))
+        self.by_type
+            .get(&TypeId::of::<R>())
+            .into_iter()
+            .flatten()
+            .map(|&id| {
+                let representation = <dyn Any>::downcast_ref(&*self.representations[&id]);
+                (id, representation.expect("type index out of sync"))
+            })
+    }
+
+    /// Returns a representation of the given type (the one with the lowest ID), or `None` if this
+    /// IR does not contain any representations of that type.
+    pub fn get_one<R: Representation>(&self) -> Option<(Id, &R)> {
+        self.get_by_representation().next()
+    }
+
+    /// Returns the representation of the given type, if this IR contains exactly one
+    /// representation of that type. Returns an error if this IR contains zero or several.
+    pub fn expect_unique<R: Representation>(&self) -> Result<(Id, &R), NotUnique> {
+        let mut iter = self.get_by_representation();
+        match (iter.next(), iter.next()) {
+            (Some(only), None) => Ok(only),
+            (None, _) => Err(NotUnique::Missing(short_type_name::<R>())),
+            (Some(_), Some(_)) => Err(NotUnique::Multiple(
+                short_type_name::<R>(),
+                self.by_type[&TypeId::of::<R>()].len(),
+            )),
+        }
     }
 
     /// Returns an iterator over the IDs and representations in this IR.
     pub fn iter(&self) -> impl Iterator<Item = (Id, &dyn Representation)> {
         self.representations.iter().map(|(&id, repr)| (id, &**repr))
     }
+
+    /// Returns the provenance of the representation with ID `id`, if it is known. Provenance is
+    /// recorded for representations written through an [edit::Organizer], and is kept for
+    /// representations that have since been superseded.
+    pub fn provenance(&self, id: Id) -> Option<&Provenance> {
+        self.provenance.get(&id).map(|p| &**p)
+    }
+
+    /// Returns the [StableKey] of the representation with ID `id`, if it has one. Keys are assigned
+    /// to the representations written by edits that set a stable producer (see
+    /// [Edit::set_stable_producer]), and are kept for representations that have since been
+    /// superseded.
+    pub fn stable_key(&self, id: Id) -> Option<StableKey> {
+        self.keys.get(&id).copied()
+    }
+
+    /// Returns the ID of the live representation with stable key `key`, if there is one. If
+    /// several live representations have the key (e.g. because a tool was run more than once on
+    /// the same inputs), returns the most recently allocated one.
+    pub fn find_by_key(&self, key: StableKey) -> Option<Id> {
+        self.keys
+            .iter()
+            .rev()
+            .find(|&(&id, &k)| k == key && self.contains_id(id))
+            .map(|(&id, _)| id)
+    }
+
+    /// Returns the IDs that the representation with ID `id` refers to (see
+    /// [Representation::references]), i.e. what it depends on. Returns nothing if there is no
+    /// (live) representation with ID `id`.
+    pub fn references(&self, id: Id) -> Vec<Id> {
+        match self.representations.get(&id) {
+            None => vec![],
+            Some(representation) => representation.references(),
+        }
+    }
+
+    /// Returns the IDs of the representations that refer to `id`, i.e. that depend on it, in ID
+    /// order.
+    pub fn referenced_by(&self, id: Id) -> impl Iterator<Item = Id> {
+        self.referenced_by.get(&id).into_iter().flatten().copied()
+    }
+
+    /// Returns the IDs of the representations that depend on `id`, directly or indirectly (by
+    /// referring to a representation that depends on `id`). Does not include `id` itself.
+    pub fn dependents(&self, id: Id) -> BTreeSet<Id> {
+        let mut dependents = BTreeSet::new();
+        let mut queue = vec![id];
+        while let Some(next) = queue.pop() {
+            for from in self.referenced_by(next) {
+                if from != id && dependents.insert(from) {
+                    queue.push(from);
+                }
+            }
+        }
+        dependents
+    }
+
+    /// Returns every reference (as a `(from, to)` pair) whose target is not a live representation
+    /// in this IR, in order. [edit::Organizer] never creates dangling references, but they can
+    /// be created by [HarvestIR::add_representation].
+    pub fn dangling_references(&self) -> Vec<(Id, Id)> {
+        self.referenced_by
+            .iter()
+            .filter(|(to, _)| !self.representations.contains_key(to))
+            .flat_map(|(&to, from)| from.iter().map(move |&from| (from, to)))
+            .collect()
+    }
+
+    /// If the representation with ID `id` has been superseded, returns the ID of the
+    /// representation that superseded it.
+    pub fn superseded_by(&self, id: Id) -> Option<Id> {
+        self.superseded.get(&id).map(|s| s.by)
+    }
+
+    /// Returns an iterator over the IDs and representations that have been superseded. These are
+    /// not returned by any other lookup method.
+    pub fn superseded(&self) -> impl Iterator<Item = (Id, &dyn Representation)> {
+        self.superseded
+            .iter()
+            .map(|(&id, s)| (id, &*s.representation))
+    }
+}
+
+/// Error type returned by [HarvestIR::expect_unique].
+#[derive(Debug, Error, Hash, PartialEq)]
+pub enum NotUnique {
+    #[error("No {0} representation found in IR")]
+    Missing(&'static str),
+    #[error("Found {1} {0} representations, expected at most 1")]
+    Multiple(&'static str, usize),
+}
+
+/// Returns the name of type `T` without its module path (used for error messages).
+fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 impl Display for HarvestIR {
@@ -93,6 +378,7 @@ impl Display for HarvestIR {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde::{Deserialize, Serialize};
     use std::collections::HashSet;
     use std::fmt::{self, Display, Formatter};
 
@@ -110,7 +396,7 @@ mod tests {
     }
 
     /// A Representation that contains only an ID number.
-    #[derive(Debug, Eq, Hash, PartialEq)]
+    #[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
     pub struct IdRepresentation(pub usize);
     impl Display for IdRepresentation {
         fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -122,6 +408,56 @@ mod tests {
             "id"
         }
     }
+    impl serialize::Serializable for IdRepresentation {
+        const KIND: &'static str = "id";
+    }
+
+    /// A Representation that refers to other representations.
+    pub struct RefRepresentation(pub Vec<Id>);
+    impl Display for RefRepresentation {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "RefRepresentation({:?})", self.0)
+        }
+    }
+    impl Representation for RefRepresentation {
+        fn name(&self) -> &'static str {
+            "ref"
+        }
+
+        fn references(&self) -> Vec<Id> {
+            self.0.clone()
+        }
+    }
+
+    /// A mergeable Representation that edits append to.
+    #[derive(Debug, PartialEq)]
+    pub struct ListRepresentation(pub Vec<u32>);
+    impl Display for ListRepresentation {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "ListRepresentation({:?})", self.0)
+        }
+    }
+    impl Representation for ListRepresentation {
+        fn name(&self) -> &'static str {
+            "list"
+        }
+
+        fn mergeable(&self) -> bool {
+            true
+        }
+
+        // Merges appends; other changes conflict.
+        fn merge(
+            &self,
+            base: &dyn Representation,
+            current: &dyn Representation,
+        ) -> Option<Box<dyn Representation>> {
+            let base = (base as &dyn Any).downcast_ref::<ListRepresentation>()?;
+            let current = (current as &dyn Any).downcast_ref::<ListRepresentation>()?;
+            let added = self.0.strip_prefix(&*base.0)?;
+            Some(Box::new(ListRepresentation([&*current.0, added].concat())))
+        }
+    }
 
     #[test]
     fn get_by_representation() {
@@ -135,4 +471,61 @@ mod tests {
             HashSet::from([(b, &IdRepresentation(1)), (d, &IdRepresentation(2))])
         );
     }
+
+    #[test]
+    fn get_one() {
+        let mut ir = HarvestIR::default();
+        assert!(ir.get_one::<IdRepresentation>().is_none());
+        assert_eq!(
+            ir.expect_unique::<IdRepresentation>(),
+            Err(NotUnique::Missing("IdRepresentation"))
+        );
+        ir.add_representation(Box::new(EmptyRepresentation));
+        let a = ir.add_representation(Box::new(IdRepresentation(1)));
+        assert_eq!(ir.get_one(), Some((a, &IdRepresentation(1))));
+        assert_eq!(ir.expect_unique(), Ok((a, &IdRepresentation(1))));
+        ir.add_representation(Box::new(IdRepresentation(2)));
+        assert_eq!(ir.get_one(), Some((a, &IdRepresentation(1))));
+        assert_eq!(
+            ir.expect_unique::<IdRepresentation>(),
+            Err(NotUnique::Multiple("IdRepresentation", 2))
+        );
+    }
+
+    #[test]
+    fn type_index() {
+        let mut ir = HarvestIR::default();
+        let a = ir.add_representation(Box::new(IdRepresentation(1)));
+        // Replacing a representation with one of a different type should move it in the index.
+        ir.insert(a, Arc::new(EmptyRepresentation));
+        assert_eq!(ir.get_by_representation::<IdRepresentation>().count(), 0);
+        assert_eq!(ir.get_by_representation::<EmptyRepresentation>().count(), 1);
+        assert!(!ir.by_type.contains_key(&TypeId::of::<IdRepresentation>()));
+        ir.insert(a, Arc::new(EmptyRepresentation));
+        assert_eq!(ir.by_type[&TypeId::of::<EmptyRepresentation>()], [a].into());
+    }
+
+    #[test]
+    fn references() {
+        let mut ir = HarvestIR::default();
+        let a = ir.add_representation(Box::new(EmptyRepresentation));
+        let b = ir.add_representation(Box::new(RefRepresentation(vec![a])));
+        let c = ir.add_representation(Box::new(RefRepresentation(vec![a, b])));
+        let d = ir.add_representation(Box::new(RefRepresentation(vec![c])));
+        assert_eq!(ir.references(c), [a, b]);
+        assert_eq!(ir.references(a), []);
+        assert_eq!(Vec::from_iter(ir.referenced_by(a)), [b, c]);
+        assert_eq!(ir.dependents(a), [b, c, d].into());
+        assert_eq!(ir.dependents(c), [d].into());
+        assert_eq!(ir.dangling_references(), []);
+
+        // Replacing or removing a representation updates the index.
+        ir.insert(c, Arc::new(RefRepresentation(vec![b])));
+        assert_eq!(Vec::from_iter(ir.referenced_by(a)), [b]);
+        ir.remove(b);
+        assert_eq!(ir.dangling_references(), [(c, b)]);
+        ir.supersede(c, d);
+        assert_eq!(ir.dangling_references(), [(d, c)]);
+        assert_eq!(ir.referenced_by.keys().copied().collect::<Vec<_>>(), [c]);
+    }
 }
@@ -0,0 +1,493 @@
+//! Saving and loading [HarvestIR]s.
+//!
+//! A [Representation] opts into serialization by implementing [Serializable], which gives it a
+//! stable `KIND` tag, and by being registered in the [Registry] passed to [HarvestIR::save] and
+//! [HarvestIR::load]. The registry maps between kind tags and the representation's concrete type.
+//!
+//! # On-disk format
+//!
+//! A saved IR is a single JSON document of the form:
+//!
+//! ```json
+//! {
+//!   "format_version": 2,
+//!   "representations": [
+//!     { "id": 1, "kind": "RawSource", "object": "9f86d081..." },
+//!     { "id": 3, "kind": "ProjectKind", "value": { ... } }
+//!   ]
+//! }
+//! ```
+//!
+//! `value` is the representation's own serde serialization. IRs saved with
+//! [HarvestIR::save_with_objects] instead store the file trees of kinds that set
+//! [Serializable::FROM_FILES] in an [ObjectStore], and refer to them by [ContentHash] in an
+//! `"object"` field, so a tree that is unchanged between saved IRs is only stored once. Version 1
+//! files, which never contain objects, can still be loaded. Representations are listed in [Id]
+//! order, and loading an IR preserves its [Id]s. Superseded representations are included, with an
+//! additional `"superseded_by"` field containing the [Id] of the representation that superseded
+//! them. Representations with a known [Provenance] have a `"provenance"` field of the form
+//! `{ "producer": "try_cargo_build_001", "version": 3, "inputs": [1, 2] }`. Representations with a
+//! [StableKey](crate::StableKey) have a `"key"` field containing the key as a hex string.
+
+use crate::fs::{ContentHash, ObjectStore, RawDir};
+use crate::{HarvestIR, Id, Provenance, Representation, StableKey};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write as _};
+use std::num::NonZeroU64;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// The version of the on-disk format written by [HarvestIR::save].
+const FORMAT_VERSION: u64 = 2;
+
+/// A [Representation] that can be saved and loaded as part of a [HarvestIR].
+pub trait Serializable: Representation + Serialize + DeserializeOwned {
+    /// Tag identifying this representation kind in saved IRs. This must be unique among the kinds
+    /// registered in a [Registry], and should not be changed once IRs containing it have been
+    /// saved.
+    const KIND: &'static str;
+
+    /// Constructs this representation from the file tree returned by [Representation::files].
+    /// Kinds that set this are saved by [HarvestIR::save_with_objects] as a reference into an
+    /// [ObjectStore] rather than inline.
+    const FROM_FILES: Option<fn(RawDir) -> Self> = None;
+}
+
+/// A set of [Serializable] representation kinds. [HarvestIR::save] can only save representations
+/// whose type is registered, and [HarvestIR::load] can only load kinds that are registered.
+#[derive(Default)]
+pub struct Registry {
+    kinds: HashMap<&'static str, Kind>,
+    kinds_by_type: HashMap<TypeId, &'static str>,
+}
+
+impl Registry {
+    /// Adds `R` to this registry. Panics if a different type has already been registered with the
+    /// same `KIND`.
+    pub fn register<R: Serializable>(&mut self) -> &mut Self {
+        match self.kinds.entry(R::KIND) {
+            Entry::Occupied(entry) if entry.get().type_id != TypeId::of::<R>() => {
+                panic!("representation kind {} registered twice", R::KIND)
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(entry) => {
+                entry.insert(Kind {
+                    type_id: TypeId::of::<R>(),
+                    serialize: serialize::<R>,
+                    deserialize: deserialize::<R>,
+                    from_files: R::FROM_FILES.map(|_| from_files::<R> as _),
+                });
+                self.kinds_by_type.insert(TypeId::of::<R>(), R::KIND);
+            }
+        }
+        self
+    }
+
+    /// Returns the kind tag for `representation`, if its type is registered.
+    fn kind_of(&self, representation: &dyn Representation) -> Option<(&'static str, &Kind)> {
+        let kind = *self
+            .kinds_by_type
+            .get(&(representation as &dyn Any).type_id())?;
+        Some((kind, &self.kinds[kind]))
+    }
+}
+
+/// Type-erased operations for a registered representation kind.
+struct Kind {
+    type_id: TypeId,
+    serialize: fn(&dyn Representation) -> serde_json::Result<Value>,
+    deserialize: fn(Value) -> serde_json::Result<Box<dyn Representation>>,
+    from_files: Option<fn(RawDir) -> Box<dyn Representation>>,
+}
+
+fn serialize<R: Serializable>(representation: &dyn Representation) -> serde_json::Result<Value> {
+    let representation: &R = (representation as &dyn Any)
+        .downcast_ref()
+        .expect("Registry::kinds_by_type returned the wrong kind");
+    serde_json::to_value(representation)
+}
+
+fn deserialize<R: Serializable>(value: Value) -> serde_json::Result<Box<dyn Representation>> {
+    Ok(Box::new(serde_json::from_value::<R>(value)?))
+}
+
+fn from_files<R: Serializable>(files: RawDir) -> Box<dyn Representation> {
+    let from_files = R::FROM_FILES.expect("from_files registered without FROM_FILES");
+    Box::new(from_files(files))
+}
+
+/// The callback [HarvestIR::save_with_objects] stores file trees with.
+type StoreObject<'s> = dyn FnMut(Id, &RawDir) -> io::Result<ContentHash> + 's;
+
+/// The top-level structure of a saved IR.
+#[derive(Deserialize, Serialize)]
+struct SavedIr {
+    format_version: u64,
+    representations: Vec<SavedRepresentation>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SavedRepresentation {
+    id: NonZeroU64,
+    kind: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    object: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    superseded_by: Option<NonZeroU64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<SavedProvenance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SavedProvenance {
+    producer: Option<String>,
+    version: u64,
+    inputs: Vec<NonZeroU64>,
+}
+
+impl HarvestIR {
+    /// Writes this IR to a new file at `path`, in the format described in the [serialize](self)
+    /// module documentation. Every representation in the IR must be registered in `registry`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, registry: &Registry) -> Result<(), SaveError> {
+        self.save_impl(path.as_ref(), registry, None)
+    }
+
+    /// Like [HarvestIR::save], but saves the files of representations whose kind sets
+    /// [Serializable::FROM_FILES] as a reference to an object instead of their contents. `store`
+    /// is called with each such representation's [Id] and files, and must store them in an
+    /// [ObjectStore] (usually with [ObjectStore::store]) and return their hash. The IR must be
+    /// loaded with [HarvestIR::load_with_objects].
+    pub fn save_with_objects<P, F>(
+        &self,
+        path: P,
+        registry: &Registry,
+        mut store: F,
+    ) -> Result<(), SaveError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Id, &RawDir) -> io::Result<ContentHash>,
+    {
+        self.save_impl(path.as_ref(), registry, Some(&mut store))
+    }
+
+    /// Implementation of [HarvestIR::save] and [HarvestIR::save_with_objects].
+    fn save_impl(
+        &self,
+        path: &Path,
+        registry: &Registry,
+        mut store: Option<&mut StoreObject>,
+    ) -> Result<(), SaveError> {
+        let mut all = Vec::from_iter(self.iter().map(|(id, r)| (id, r, None)));
+        all.extend(
+            self.superseded()
+                .map(|(id, r)| (id, r, self.superseded_by(id))),
+        );
+        all.sort_by_key(|&(id, _, _)| id);
+        let mut representations = Vec::with_capacity(all.len());
+        for (id, representation, superseded_by) in all {
+            let Some((kind, ops)) = registry.kind_of(representation) else {
+                return Err(SaveError::NotSerializable {
+                    id,
+                    name: representation.name(),
+                });
+            };
+            let (value, object) = match (&mut store, representation.files()) {
+                (Some(store), Some(files)) if ops.from_files.is_some() => {
+                    (Value::Null, Some(store(id, files)?.to_string()))
+                }
+                _ => ((ops.serialize)(representation)?, None),
+            };
+            representations.push(SavedRepresentation {
+                id: id.into(),
+                kind: kind.into(),
+                value,
+                object,
+                superseded_by: superseded_by.map(Into::into),
+                provenance: self.provenance(id).map(|p| SavedProvenance {
+                    producer: p.producer.clone(),
+                    version: p.version,
+                    inputs: p.inputs.iter().map(|&id| id.into()).collect(),
+                }),
+                key: self.stable_key(id).map(|key| key.to_string()),
+            });
+        }
+        let saved = SavedIr {
+            format_version: FORMAT_VERSION,
+            representations,
+        };
+        let mut writer = BufWriter::new(File::create_new(path)?);
+        serde_json::to_writer(&mut writer, &saved)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads an IR previously written by [HarvestIR::save]. Every representation kind in the file
+    /// must be registered in `registry`.
+    pub fn load<P: AsRef<Path>>(path: P, registry: &Registry) -> Result<HarvestIR, LoadError> {
+        Self::load_impl(path.as_ref(), registry, None)
+    }
+
+    /// Reads an IR previously written by [HarvestIR::save_with_objects] (or [HarvestIR::save]),
+    /// loading referenced file trees from `objects`.
+    pub fn load_with_objects<P: AsRef<Path>>(
+        path: P,
+        registry: &Registry,
+        objects: &ObjectStore,
+    ) -> Result<HarvestIR, LoadError> {
+        Self::load_impl(path.as_ref(), registry, Some(objects))
+    }
+
+    /// Implementation of [HarvestIR::load] and [HarvestIR::load_with_objects].
+    fn load_impl(
+        path: &Path,
+        registry: &Registry,
+        objects: Option<&ObjectStore>,
+    ) -> Result<HarvestIR, LoadError> {
+        let saved: SavedIr = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if !(1..=FORMAT_VERSION).contains(&saved.format_version) {
+            return Err(LoadError::UnsupportedVersion(saved.format_version));
+        }
+        let mut ir = HarvestIR::default();
+        let mut supersedes = vec![];
+        for saved in saved.representations {
+            let SavedRepresentation {
+                id,
+                kind,
+                value,
+                object,
+                superseded_by,
+                provenance,
+                key,
+            } = saved;
+            let Some(ops) = registry.kinds.get(&*kind) else {
+                return Err(LoadError::UnknownKind(kind));
+            };
+            let id = Id::reserve(id);
+            let representation = match object {
+                None => (ops.deserialize)(value)?,
+                Some(object) => {
+                    let (Some(objects), Some(from_files)) = (objects, ops.from_files) else {
+                        return Err(LoadError::UnresolvedObject(id));
+                    };
+                    let hash: ContentHash = object
+                        .parse()
+                        .map_err(|_| LoadError::InvalidObject(object))?;
+                    from_files(objects.load(hash)?)
+                }
+            };
+            if ir.insert(id, Arc::from(representation)).is_some() {
+                return Err(LoadError::DuplicateId(id));
+            }
+            if let Some(p) = provenance {
+                let provenance = Provenance {
+                    producer: p.producer,
+                    version: p.version,
+                    inputs: p.inputs.into_iter().map(Id::reserve).collect(),
+                };
+                ir.provenance.insert(id, provenance.into());
+            }
+            if let Some(key) = key {
+                let key: StableKey = key.parse().map_err(|_| LoadError::InvalidKey(key))?;
+                ir.keys.insert(id, key);
+            }
+            if let Some(by) = superseded_by {
+                supersedes.push((id, Id::reserve(by)));
+            }
+        }
+        for (old, new) in supersedes {
+            ir.supersede(old, new);
+        }
+        Ok(ir)
+    }
+}
+
+/// Error type returned by [HarvestIR::save].
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("serialization failed: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("representation {id} ({name}) is not a registered Serializable kind")]
+    NotSerializable { id: Id, name: &'static str },
+}
+
+/// Error type returned by [HarvestIR::load].
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("{0} appears more than once")]
+    DuplicateId(Id),
+    #[error("invalid stable key {0:?}")]
+    InvalidKey(String),
+    #[error("invalid object hash {0:?}")]
+    InvalidObject(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("deserialization failed: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unknown representation kind {0:?}")]
+    UnknownKind(String),
+    #[error(
+        "{0} is stored as an object, but no object store was given or its kind has no FROM_FILES"
+    )]
+    UnresolvedObject(Id),
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u64),
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+    use crate::tests::{EmptyRepresentation, IdRepresentation};
+
+    fn registry() -> Registry {
+        let mut registry = Registry::default();
+        registry.register::<IdRepresentation>();
+        registry
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ir.json");
+        let mut ir = HarvestIR::default();
+        let a = ir.add_representation(Box::new(IdRepresentation(1)));
+        let b = ir.add_representation(Box::new(IdRepresentation(2)));
+        let c = ir.add_representation(Box::new(IdRepresentation(3)));
+        ir.supersede(a, c);
+        let provenance = Provenance {
+            producer: Some("tool_001".into()),
+            version: 2,
+            inputs: [b].into(),
+        };
+        ir.provenance.insert(c, provenance.clone().into());
+        let key = StableKey::derive("tool", [], "IdRepresentation", 0);
+        ir.keys.insert(c, key);
+        ir.save(&path, &registry()).expect("save failed");
+        let loaded = HarvestIR::load(&path, &registry()).expect("load failed");
+        assert_eq!(
+            Vec::from_iter(loaded.get_by_representation::<IdRepresentation>()),
+            [(b, &IdRepresentation(2)), (c, &IdRepresentation(3))]
+        );
+        assert_eq!(loaded.superseded_by(a), Some(c));
+        assert_eq!(loaded.provenance(a), None);
+        assert_eq!(loaded.provenance(c), Some(&provenance));
+        assert_eq!(loaded.stable_key(a), None);
+        assert_eq!(loaded.stable_key(c), Some(key));
+        assert_eq!(
+            Vec::from_iter(loaded.superseded().map(|(id, r)| (id, r.to_string()))),
+            [(a, "IdRepresentation(1)".into())]
+        );
+        // Loaded IDs must not be handed out again.
+        assert!(Id::new() > c);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ir = HarvestIR::default();
+        let id = ir.add_representation(Box::new(EmptyRepresentation));
+        assert!(matches!(
+            ir.save(dir.path().join("a.json"), &registry()),
+            Err(SaveError::NotSerializable { id: i, name: "empty" }) if i == id
+        ));
+
+        let mut ir = HarvestIR::default();
+        ir.add_representation(Box::new(IdRepresentation(1)));
+        ir.save(dir.path().join("b.json"), &registry()).unwrap();
+        assert!(matches!(
+            HarvestIR::load(dir.path().join("b.json"), &Registry::default()),
+            Err(LoadError::UnknownKind(kind)) if kind == "id"
+        ));
+        assert!(matches!(
+            ir.save(dir.path().join("b.json"), &registry()),
+            Err(SaveError::Io(_))
+        ));
+    }
+
+    #[derive(Deserialize, PartialEq, Serialize)]
+    struct Files {
+        dir: RawDir,
+    }
+    impl std::fmt::Display for Files {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "Files")
+        }
+    }
+    impl Representation for Files {
+        fn name(&self) -> &'static str {
+            "files"
+        }
+        fn files(&self) -> Option<&RawDir> {
+            Some(&self.dir)
+        }
+    }
+    impl Serializable for Files {
+        const KIND: &'static str = "files";
+        const FROM_FILES: Option<fn(RawDir) -> Self> = Some(|dir| Files { dir });
+    }
+
+    #[test]
+    fn objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = ObjectStore::new(dir.path().join("objects"));
+        let mut registry = registry();
+        registry.register::<Files>();
+        let mut files = RawDir::default();
+        files.set_file("a.c", b"int a;\n".into()).unwrap();
+        let mut ir = HarvestIR::default();
+        let id = ir.add_representation(Box::new(Files { dir: files.clone() }));
+        ir.add_representation(Box::new(IdRepresentation(1)));
+        let path = dir.path().join("ir.json");
+        ir.save_with_objects(&path, &registry, |_, files| objects.store(files))
+            .unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("int a;"));
+        assert!(saved.contains(&files.content_hash().to_string()));
+
+        let loaded = HarvestIR::load_with_objects(&path, &registry, &objects).unwrap();
+        let (loaded_id, loaded_files) = loaded.get_one::<Files>().unwrap();
+        assert_eq!(loaded_id, id);
+        assert_eq!(loaded_files.dir.get_file("a.c"), Ok(b"int a;\n".as_slice()));
+        assert_eq!(
+            loaded.get_by_representation::<IdRepresentation>().count(),
+            1
+        );
+        assert!(matches!(
+            HarvestIR::load(&path, &registry),
+            Err(LoadError::UnresolvedObject(i)) if i == id
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn duplicate_kind() {
+        #[derive(Deserialize, Serialize)]
+        struct Other;
+        impl std::fmt::Display for Other {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "Other")
+            }
+        }
+        impl Representation for Other {
+            fn name(&self) -> &'static str {
+                "other"
+            }
+        }
+        impl Serializable for Other {
+            const KIND: &'static str = "id";
+        }
+        registry().register::<Other>();
+    }
+}
@@ -0,0 +1,10 @@
+use clap::Parser;
+use harvest_translate::inspect::{Args, inspect};
+use std::io::stdout;
+
+fn main() {
+    if let Err(e) = inspect(&Args::parse(), &mut stdout().lock()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -4,6 +4,8 @@
 use crate::tools;
 use clap::Parser;
 use config::FileFormat::Toml;
+use config::builder::{ConfigBuilder, DefaultState};
+use config::{Map, ValueKind};
 use directories::ProjectDirs;
 use serde::Deserialize;
 use serde_json::Value;
@@ -22,7 +24,8 @@ pub struct Args {
     #[arg(long, short)]
     pub force: bool,
 
-    /// Path to the directory containing the C code to translate.
+    /// Path to the directory containing the C code to translate, or to an archive of it (`.tar`,
+    /// `.tar.gz`, `.tgz`, or `.zip`).
     // Should always be present unless using a subcommand like --print-config-path
     pub input: Option<PathBuf>,
 
@@ -33,6 +36,17 @@ pub struct Args {
     /// Path to output directory containing the translated Rust code.
     #[arg(short, long)]
     pub output: Option<PathBuf>,
+
+    /// Name of a tool to run again even if its outputs are present in the resumed IR. May be
+    /// repeated. Only meaningful with --resume.
+    #[arg(long, requires = "resume")]
+    pub rerun: Vec<String>,
+
+    /// Resume from a previous run's diagnostics directory, rather than starting from scratch. This
+    /// may be either the diagnostics directory itself (to resume from its latest IR version) or a
+    /// specific IR version within it (e.g. `diagnostics/ir/003`).
+    #[arg(long)]
+    pub resume: Option<PathBuf>,
 }
 
 /// Configuration for this harvest-translate run. The sources of these configuration values (from
@@ -43,7 +57,9 @@ pub struct Args {
 /// 3. Defaults specified in the code (using `#[serde(default)]`).
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    /// Path to the directory containing the C code to translate.
+    /// Path to the directory containing the C code to translate, or to an archive of it. May only
+    /// be empty when resuming a previous run.
+    #[serde(default)]
     pub input: PathBuf,
 
     /// Path to output directory.
@@ -64,9 +80,30 @@ pub struct Config {
     /// `tracing_subscriber::filter::EnvFilter` format.
     pub log_filter: String,
 
+    /// The name of the pipeline (in `pipelines`) to run.
+    pub pipeline: String,
+
+    /// Named pipelines, each of which lists the names of the tools to queue (and optionally
+    /// overrides their parameters).
+    pub pipelines: HashMap<String, PipelineConfig>,
+
+    /// If true, translate fails as soon as a tool's edit violates an IR invariant. If false,
+    /// invariant violations are only reported in the diagnostics.
+    pub strict_invariants: bool,
+
     /// Sub-configuration for each tool.
     pub tools: tools::ToolConfigs,
 
+    /// Names of tools to run even if their outputs are already present in the resumed IR. Set by
+    /// the `--rerun` command line flag.
+    #[serde(skip)]
+    pub rerun: Vec<String>,
+
+    /// Diagnostics directory (or IR version within one) to resume from. Set by the `--resume`
+    /// command line flag.
+    #[serde(skip)]
+    pub resume: Option<PathBuf>,
+
     // serde will place any unrecognized fields here. This will be passed to unknown_field_warning
     // after parsing to emit warnings on unrecognized config entries (we don't error on unknown
     // fields because that can be annoying to work with if you are switching back and forth between
@@ -84,7 +121,40 @@ impl Config {
             diagnostics_dir: None,
             force: false,
             log_filter: "off".to_owned(),
+            pipeline: "mock".to_owned(),
+            pipelines: [("mock".to_owned(), PipelineConfig::new(vec![]))].into(),
+            strict_invariants: false,
             tools: tools::ToolConfigs::mock(),
+            rerun: vec![],
+            resume: None,
+            unknown: HashMap::new(),
+        }
+    }
+}
+
+/// A named pipeline (see [Config::pipelines]).
+#[derive(Debug, Deserialize)]
+pub struct PipelineConfig {
+    /// The names of the tools to queue. Tools run in the order their inputs become available, not
+    /// in the order they are listed.
+    pub tools: Vec<String>,
+
+    /// Tool parameters to use when this pipeline is selected, keyed by tool name. When the config
+    /// is loaded, each table is merged over the tool's `[tools.<tool>]` table, so [Config::tools]
+    /// already contains the selected pipeline's overrides.
+    #[serde(default)]
+    pub overrides: HashMap<String, Value>,
+
+    #[serde(flatten)]
+    unknown: HashMap<String, Value>,
+}
+
+impl PipelineConfig {
+    /// Returns a pipeline that queues the tools in `tools`, without overrides.
+    pub fn new(tools: Vec<String>) -> Self {
+        Self {
+            tools,
+            overrides: HashMap::new(),
             unknown: HashMap::new(),
         }
     }
@@ -116,6 +186,11 @@ pub fn initialize(args: Arc<Args>) -> Option<Config> {
     }
     let config = load_config(&args, dirs.config_dir());
     unknown_field_warning("", &config.unknown);
+    let mut pipelines: Vec<_> = config.pipelines.iter().collect();
+    pipelines.sort_unstable_by_key(|&(name, _)| name);
+    for (name, pipeline) in pipelines {
+        unknown_field_warning(&format!("pipelines.{name}"), &pipeline.unknown);
+    }
     config.tools.validate();
     Some(config)
 }
@@ -159,20 +234,62 @@ fn load_config(args: &Args, config_dir: &Path) -> Config {
             .expect("settings override failed");
     }
 
-    let mut config: Config = settings
-        .build()
-        .expect("failed to build settings")
+    let settings = settings.build().expect("failed to build settings");
+    let mut config: Config = apply_pipeline_overrides(settings)
         .try_deserialize()
         .expect("config deserialization failed");
     if let Some(ref input) = args.input {
         config.input = input.clone();
     }
+    // When resuming, the input is loaded from the previous run's IR, so it does not need to be
+    // specified.
+    if config.input.as_os_str().is_empty() && args.resume.is_none() {
+        panic!("no input directory specified");
+    }
     if let Some(ref output) = args.output {
         config.output = output.clone();
     }
+    config.rerun = args.rerun.clone();
+    config.resume = args.resume.clone();
     config
 }
 
+/// Merges the selected pipeline's `[pipelines.<name>.overrides.<tool>]` tables over the
+/// corresponding `[tools.<tool>]` tables.
+fn apply_pipeline_overrides(settings: config::Config) -> config::Config {
+    let overrides = settings
+        .get_string("pipeline")
+        .ok()
+        .and_then(|name| settings.get_table("pipelines").ok()?.remove(&name))
+        .and_then(|pipeline| pipeline.into_table().ok()?.remove("overrides"))
+        .and_then(|overrides| overrides.into_table().ok());
+    let Some(overrides) = overrides else {
+        return settings;
+    };
+    let builder = config::Config::builder().add_source(settings);
+    set_overrides(builder, "tools", overrides)
+        .build()
+        .expect("failed to build settings")
+}
+
+/// Sets an override for each non-table value in `table` (recursively), at its path under `prefix`.
+/// Tables are merged rather than replaced, so parameters that are not overridden keep their values.
+fn set_overrides(
+    builder: ConfigBuilder<DefaultState>,
+    prefix: &str,
+    table: Map<String, config::Value>,
+) -> ConfigBuilder<DefaultState> {
+    table.into_iter().fold(builder, |builder, (key, value)| {
+        let path = format!("{prefix}.{key}");
+        match value.kind {
+            ValueKind::Table(table) => set_overrides(builder, &path, table),
+            _ => builder
+                .set_override(path, value)
+                .expect("settings override failed"),
+        }
+    })
+}
+
 /// Returns the config file path, given the config directory.
 fn config_file(config_dir: &Path) -> PathBuf {
     [config_dir, "translate.toml".as_ref()].iter().collect()
@@ -199,9 +316,13 @@ mod tests {
 
         fs::File::create(config_file(config_dir.path()))
             .unwrap()
-            .write(
+            .write_all(
                 br#"
                     input = "b"
+                    [pipelines.load-only]
+                    tools = ["load_raw_source"]
+                    [pipelines.load-only.overrides.load_raw_source]
+                    lazy = true
                     [tools.raw_source_to_cargo_llm]
                     address = "127.0.0.1"
                     model = "gpt-oss"
@@ -234,6 +355,31 @@ mod tests {
             .input,
             AsRef::<Path>::as_ref("d")
         );
+        // Verify that pipelines from the user's config file are added to the default pipelines,
+        // and that all of them name registered tools.
+        let config = load_config(
+            &Args::parse_from(["", "--config", "pipeline=load-only", "--output=/tmp/out"]),
+            config_dir.path(),
+        );
+        let tools = tools::registry(&config).pipeline().unwrap();
+        assert_eq!(tools.len(), 1);
+        // The pipeline's overrides are merged over the tool's parameters.
+        assert!(config.tools.load_raw_source.lazy);
+        // Filtering beyond the default excludes is opt-in.
+        assert!(!config.tools.load_raw_source.gitignore);
+        assert_eq!(config.tools.load_raw_source.max_file_size, None);
+        for pipeline in ["llm-direct", "llm-no-build"] {
+            assert!(config.pipelines[pipeline].tools.len() > 1);
+            let config = Config {
+                pipeline: pipeline.into(),
+                ..load_config(
+                    &Args::parse_from(["", "--output=/tmp/out"]),
+                    config_dir.path(),
+                )
+            };
+            assert!(!config.tools.load_raw_source.lazy);
+            tools::registry(&config).pipeline().unwrap();
+        }
         // Verify --force enables the force option.
         assert!(
             load_config(
@@ -0,0 +1,176 @@
+//! The format of the `ir/NNN/index` diagnostics files, which list the representations in each IR
+//! version. Each line of an index describes one representation, for example:
+//!
+//! ```text
+//! 003: CargoPackage [9f9b63231d305bc1fc10e60e6c1ed8e1] (by raw_source_to_cargo_llm_001 in version 003, from 001, 002)
+//! ```
+//!
+//! The stable key (in brackets) and provenance (in parentheses) are omitted if the representation
+//! does not have them.
+
+use harvest_ir::{HarvestIR, Id, StableKey};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A single line of an IR index.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexEntry {
+    /// The representation's ID.
+    pub id: u64,
+    /// The representation's name ([Representation::name](harvest_ir::Representation::name)).
+    pub name: String,
+    pub key: Option<StableKey>,
+    pub provenance: Option<IndexProvenance>,
+}
+
+/// The [Provenance](harvest_ir::Provenance) of a representation, as listed in an IR index.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexProvenance {
+    /// The tool run that wrote the representation, if it is known.
+    pub producer: Option<String>,
+    /// The IR version in which the representation was written.
+    pub version: u64,
+    /// The IDs of the representations it was derived from.
+    pub inputs: Vec<u64>,
+}
+
+impl IndexEntry {
+    /// Returns the index entry for the representation `id`, named `name`, in `ir`.
+    pub(crate) fn new(ir: &HarvestIR, id: Id, name: &str) -> IndexEntry {
+        IndexEntry {
+            id: id.into(),
+            name: name.into(),
+            key: ir.stable_key(id),
+            provenance: ir.provenance(id).map(|provenance| IndexProvenance {
+                producer: provenance.producer.clone(),
+                version: provenance.version,
+                inputs: provenance.inputs.iter().map(|&id| id.into()).collect(),
+            }),
+        }
+    }
+}
+
+impl Display for IndexEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:03}: {}", self.id, self.name)?;
+        if let Some(key) = self.key {
+            write!(f, " [{key}]")?;
+        }
+        if let Some(provenance) = &self.provenance {
+            let producer = provenance.producer.as_deref().unwrap_or(UNKNOWN_PRODUCER);
+            write!(f, " (by {producer} in version {:03}", provenance.version)?;
+            for (i, input) in provenance.inputs.iter().enumerate() {
+                let separator = if i == 0 { ", from " } else { ", " };
+                write!(f, "{separator}{input:03}")?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+/// What the index lists as the producer of representations whose producer is not known.
+const UNKNOWN_PRODUCER: &str = "unknown producer";
+
+impl FromStr for IndexEntry {
+    type Err = ParseIndexError;
+
+    fn from_str(line: &str) -> Result<IndexEntry, ParseIndexError> {
+        let error = || ParseIndexError(line.into());
+        let (id, rest) = line.split_once(": ").ok_or_else(error)?;
+        let id = id.parse().map_err(|_| error())?;
+        let (rest, provenance) = match rest.strip_suffix(')') {
+            None => (rest, None),
+            Some(rest) => {
+                let (rest, provenance) = rest.split_once(" (by ").ok_or_else(error)?;
+                (rest, Some(provenance))
+            }
+        };
+        let (name, key) = match rest.strip_suffix(']') {
+            None => (rest, None),
+            Some(rest) => {
+                let (name, key) = rest.split_once(" [").ok_or_else(error)?;
+                (name, Some(key.parse().map_err(|_| error())?))
+            }
+        };
+        let provenance = match provenance {
+            None => None,
+            Some(provenance) => {
+                let (producer, rest) = provenance.split_once(" in version ").ok_or_else(error)?;
+                let mut fields = rest.split(", ");
+                let version = fields.next().ok_or_else(error)?;
+                let mut inputs = vec![];
+                for (i, field) in fields.enumerate() {
+                    let input = match i {
+                        0 => field.strip_prefix("from ").ok_or_else(error)?,
+                        _ => field,
+                    };
+                    inputs.push(input.parse().map_err(|_| error())?);
+                }
+                Some(IndexProvenance {
+                    producer: Some(producer.to_owned()).filter(|p| p != UNKNOWN_PRODUCER),
+                    version: version.parse().map_err(|_| error())?,
+                    inputs,
+                })
+            }
+        };
+        Ok(IndexEntry {
+            id,
+            name: name.into(),
+            key,
+            provenance,
+        })
+    }
+}
+
+/// Error returned when parsing an invalid IR index line.
+#[derive(Debug, Eq, Error, PartialEq)]
+#[error("invalid IR index line {0:?}")]
+pub struct ParseIndexError(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = "9f9b63231d305bc1fc10e60e6c1ed8e1";
+        for line in [
+            "001: RawSource".to_owned(),
+            format!("002: KindAndName [{key}]"),
+            "003: KindAndName (by unknown producer in version 001)".to_owned(),
+            format!("004: CargoPackage [{key}] (by tool_a_001 in version 012, from 001, 003)"),
+        ] {
+            let entry: IndexEntry = line.parse().unwrap();
+            assert_eq!(entry.to_string(), line);
+        }
+        let entry: IndexEntry = "004: CargoPackage (by tool_a_001 in version 012, from 001, 003)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            entry,
+            IndexEntry {
+                id: 4,
+                name: "CargoPackage".into(),
+                key: None,
+                provenance: Some(IndexProvenance {
+                    producer: Some("tool_a_001".into()),
+                    version: 12,
+                    inputs: vec![1, 3],
+                }),
+            }
+        );
+        for invalid in [
+            "",
+            "x: RawSource",
+            "001: RawSource (by x)",
+            "001: RawSource [xyz]",
+        ] {
+            assert_eq!(
+                invalid.parse::<IndexEntry>(),
+                Err(ParseIndexError(invalid.into()))
+            );
+        }
+    }
+}
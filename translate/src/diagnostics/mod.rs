@@ -7,16 +7,21 @@
 //! This module also provides directories for tools to use, as those directories live under the
 //! diagnostic directory.
 
+mod index;
 #[cfg(all(not(miri), test))]
 mod tests;
 mod tool_reporter;
 
 use crate::cli::Config;
-use crate::tools::Tool;
+use crate::scheduler::DependencyGraph;
+use crate::tools::{self, Tool};
 use crate::util::{EmptyDirError, empty_writable_dir};
-use harvest_ir::HarvestIR;
+use harvest_ir::fs::{ContentHash, ObjectStore, RawDir};
+use harvest_ir::invariant::Violation;
+use harvest_ir::serialize;
+use harvest_ir::{HarvestIR, Id};
 use std::collections::HashMap;
-use std::fmt::{Arguments, Write as _};
+use std::fmt::{self, Arguments, Display, Formatter, Write as _};
 use std::fs::{File, canonicalize, create_dir, write};
 use std::io::{self, IoSlice, Write};
 use std::mem::replace;
@@ -24,6 +29,7 @@ use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender, channel};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use tempfile::{TempDir, tempdir};
 use thiserror::Error;
 use tool_reporter::ToolId;
@@ -33,24 +39,91 @@ use tracing_subscriber::fmt::{MakeWriter, layer};
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::{EnvFilter, Layer as _, Registry};
 
+pub use index::{IndexEntry, IndexProvenance, ParseIndexError};
 pub(crate) use tool_reporter::ToolJoiner;
 pub use tool_reporter::ToolReporter;
 
+/// Name of the file (within each `ir/NNN` diagnostics directory) that the IR is saved into using
+/// [HarvestIR::save_with_objects].
+pub(crate) const IR_FILE_NAME: &str = "ir.json";
+
+/// Name of the diagnostics directory's object store (see [ObjectStore]), which the saved IRs'
+/// file trees are stored in.
+pub(crate) const OBJECTS_DIR_NAME: &str = "objects";
+
 /// Diagnostics produced by transpilation. Can be used by callers of `transpile` to inspect the
 /// diagnostics produced during its execution.
+#[derive(Debug)]
 pub struct Diagnostics {
-    // TODO: Figure out what we want to have here, versus only on disk. From
-    // https://github.com/betterbytes-org/harvest-code/issues/51#issuecomment-3524208160,
-    // we at least want information on tool invocation results (successes and errors with error
-    // messages).
-
+    /// Every tool run that was launched, in the order they completed.
+    pub tool_runs: Vec<ToolRunRecord>,
     // TODO: If this needs to access the diagnostics directory, then we need to move
     // Option<TempDir> from `Collector` into here.
 }
 
 impl Diagnostics {
     fn new() -> Diagnostics {
-        Diagnostics {}
+        Diagnostics { tool_runs: vec![] }
+    }
+
+    /// Returns the tool runs that returned an error or panicked. Runs whose edit was rejected are
+    /// not included, as they may have been retried.
+    pub fn failed_tool_runs(&self) -> impl Iterator<Item = &ToolRunRecord> {
+        self.tool_runs.iter().filter(|run| {
+            matches!(
+                run.result,
+                ToolRunResult::Failed(_) | ToolRunResult::Panicked(_)
+            )
+        })
+    }
+}
+
+/// The record of a single tool run, in [Diagnostics::tool_runs].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ToolRunRecord {
+    /// The tool's name ([Tool::name]).
+    pub name: &'static str,
+    /// The first run of a particular tool has number 1, the second has 2, etc.
+    pub number: u64,
+    /// How long [Tool::run] took.
+    pub duration: Duration,
+    pub result: ToolRunResult,
+    /// The IR version the tool was launched with.
+    pub start_version: u64,
+    /// The IR version created by the tool's edit, or `None` if its edit was not applied.
+    pub end_version: Option<u64>,
+}
+
+impl ToolRunRecord {
+    /// Returns the ID of this tool run (e.g. `try_cargo_build_002`), which is also the name of its
+    /// diagnostics directory.
+    pub fn tool_run_id(&self) -> String {
+        format!("{}_{:03}", self.name, self.number)
+    }
+}
+
+/// How a tool run ended.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ToolRunResult {
+    /// The tool succeeded and its edit was applied.
+    Succeeded,
+    /// [Tool::run] returned an error, with this message.
+    Failed(String),
+    /// [Tool::run] panicked, with this payload (if it was a string).
+    Panicked(Option<String>),
+    /// The tool succeeded, but its edit was rejected (e.g. because it was stale), for this reason.
+    EditRejected(String),
+}
+
+impl Display for ToolRunResult {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ToolRunResult::Succeeded => f.write_str("succeeded"),
+            ToolRunResult::Failed(message) => write!(f, "failed: {message}"),
+            ToolRunResult::Panicked(None) => f.write_str("panicked"),
+            ToolRunResult::Panicked(Some(payload)) => write!(f, "panicked: {payload}"),
+            ToolRunResult::EditRejected(reason) => write!(f, "edit rejected: {reason}"),
+        }
     }
 }
 
@@ -109,9 +182,13 @@ impl Collector {
             shared: Arc::new(Mutex::new(Shared {
                 console_filter,
                 diagnostics: Diagnostics::new(),
+                objects: ObjectStore::new(diagnostics_dir.join(OBJECTS_DIR_NAME)),
+                object_hashes: HashMap::new(),
                 diagnostics_dir,
                 diagnostics_sender,
                 messages_file,
+                previous_ir: None,
+                representations: tools::representations(),
                 tool_run_counts: HashMap::new(),
             })),
             _tempdir,
@@ -147,8 +224,32 @@ pub(crate) struct Reporter {
 
 impl Reporter {
     /// Reports a new version of the IR.
+    ///
+    /// Representations are materialized into the version's directory, except for file trees (see
+    /// [Representation::files](harvest_ir::Representation::files)), which are stored once in the
+    /// content-addressed `objects` directory and linked to from each version. File trees that
+    /// existed in the previously-reported version (either under the same ID or as the
+    /// representation they superseded) are also written as a unified diff against their previous
+    /// contents, into the version's `diff` file.
+    ///
+    /// Each file tree is hashed at most once per version, and not at all if it has not been
+    /// rewritten since the previous version (see [Shared::object_hashes]).
     pub fn report_ir_version(&self, version: u64, snapshot: &HarvestIR) {
-        let shared = lock_shared(&self.shared);
+        let mut shared = lock_shared(&self.shared);
+        let mut hashes = HashMap::new();
+        let mut store = |id, files: &RawDir| -> io::Result<ContentHash> {
+            let version = snapshot.provenance(id).map(|provenance| provenance.version);
+            let known = hashes
+                .get(&(id, version))
+                .or_else(|| shared.object_hashes.get(&(id, version?)));
+            let hash = match known {
+                Some(&hash) => hash,
+                None => files.try_content_hash()?,
+            };
+            hashes.insert((id, version), hash);
+            shared.objects.insert(hash, files)?;
+            Ok(hash)
+        };
         let mut path = shared.diagnostics_dir.clone();
         path.push("ir");
         path.push(format!("{version:03}"));
@@ -156,28 +257,145 @@ impl Reporter {
             error!("Failed to create IR directory: {error}");
             return;
         }
+        let previous: HashMap<_, _> = shared.previous_ir.iter().flat_map(|ir| ir.iter()).collect();
+        let superseded: HashMap<_, _> = snapshot
+            .superseded()
+            .filter_map(|(old, _)| Some((snapshot.superseded_by(old)?, old)))
+            .filter(|(_, old)| previous.contains_key(old))
+            .collect();
         let mut types = vec![];
+        let mut diff = String::new();
         for (id, repr) in snapshot.iter() {
             let id_string = format!("{:03}", Into::<u64>::into(id));
+            let old_id = match previous.contains_key(&id) {
+                true => Some(id),
+                false => superseded.get(&id).copied(),
+            };
+            let old_files = old_id.and_then(|old_id| Some((old_id, previous[&old_id].files()?)));
+            if let (Some((old_id, old_files)), Some(files)) = (old_files, repr.files()) {
+                match old_files.try_diff(files) {
+                    Ok(changes) if changes.changes.is_empty() => {}
+                    Ok(changes) => {
+                        let _ = writeln!(diff, "diff {:03} {id_string}", u64::from(old_id));
+                        let _ = write!(diff, "{changes}");
+                    }
+                    Err(error) => error!("Failed to diff repr {id_string}: {error}"),
+                }
+            }
             path.push(&id_string);
-            if let Err(error) = repr.materialize(&path) {
+            let result = match repr.files() {
+                Some(files) => store(id, files).and_then(|hash| link_object(hash, files, &path)),
+                None => repr.materialize(&path),
+            };
+            if let Err(error) = result {
                 error!("Failed to materialize repr: {error}");
             }
             path.pop();
-            types.push((id, id_string, repr.name()));
+            types.push((id, repr.name()));
+        }
+        if !diff.is_empty() {
+            path.push("diff");
+            if let Err(error) = write(&path, diff) {
+                error!("Failed to write IR diff: {error}");
+            }
+            path.pop();
         }
         // TODO: For now, HarvestIR does not guarantee a particular iteration order, but it
         // *happens* to iterate in this same order. We should figure out what guarantees we want
         // HarvestIR to have, and then update this accordingly.
         types.sort_unstable_by_key(|t| t.0);
         let mut index = String::new();
-        for (_, id_string, name) in types {
-            let _ = writeln!(index, "{id_string}: {name}");
+        for (id, name) in types {
+            let _ = writeln!(index, "{}", IndexEntry::new(snapshot, id, name));
         }
         path.push("index");
-        if let Err(error) = write(path, index) {
+        if let Err(error) = write(&path, index) {
             error!("Failed to write IR index: {error}");
         }
+        // Save the IR in a loadable form as well, so that a later run can resume from it. File
+        // trees are saved as references to the objects stored above.
+        path.set_file_name(IR_FILE_NAME);
+        let result = snapshot.save_with_objects(&path, &shared.representations, &mut store);
+        if let Err(error) = result {
+            error!("Failed to save IR: {error}");
+        }
+        shared.object_hashes = hashes
+            .into_iter()
+            .filter_map(|((id, version), hash)| Some(((id, version?), hash)))
+            .collect();
+        shared.previous_ir = Some(snapshot.clone());
+    }
+
+    /// Reports the dependency graph between the tools `transpile` queued, by writing it to the
+    /// `pipeline` file.
+    pub fn report_pipeline(&self, pipeline: &DependencyGraph) {
+        let shared = lock_shared(&self.shared);
+        if let Err(error) = write(
+            shared.diagnostics_dir.join("pipeline"),
+            pipeline.to_string(),
+        ) {
+            error!("Failed to write pipeline: {error}");
+        }
+    }
+
+    /// Reports that IR version `version`, produced by the tool run `tool_run`, violates IR
+    /// invariants. The violations are logged and written to `ir/NNN/violations`.
+    pub fn report_invariant_violations(
+        &self,
+        version: u64,
+        tool_run: &str,
+        violations: &[Violation],
+    ) {
+        let mut contents = String::new();
+        for violation in violations {
+            error!("IR version {version} (from {tool_run}) violates {violation}");
+            let _ = writeln!(contents, "{violation}");
+        }
+        let shared = lock_shared(&self.shared);
+        let mut path = shared.diagnostics_dir.clone();
+        path.push("ir");
+        path.push(format!("{version:03}"));
+        path.push("violations");
+        if let Err(error) = write(&path, contents) {
+            error!("Failed to write invariant violations: {error}");
+        }
+    }
+
+    /// Reports that the tool run `tool_run` was launched with IR version `version`, by linking
+    /// `steps/<tool_run>/start_ir` to that version's directory.
+    pub fn report_tool_run_start(&self, tool_run: &str, version: u64) {
+        self.link_ir_version(tool_run, "start_ir", version);
+    }
+
+    /// Reports that the edit made by the tool run `tool_run` created IR version `version`, by
+    /// linking `steps/<tool_run>/end_ir` to that version's directory.
+    pub fn report_tool_run_end(&self, tool_run: &str, version: u64) {
+        self.link_ir_version(tool_run, "end_ir", version);
+    }
+
+    /// Creates the link `steps/<tool_run>/<name>`, pointing to IR version `version`. On platforms
+    /// without symlinks, this is a file containing the link's target instead.
+    fn link_ir_version(&self, tool_run: &str, name: &str, version: u64) {
+        let shared = lock_shared(&self.shared);
+        let link = PathBuf::from_iter([
+            shared.diagnostics_dir.as_path(),
+            "steps".as_ref(),
+            tool_run.as_ref(),
+            name.as_ref(),
+        ]);
+        let target = PathBuf::from(format!("../../ir/{version:03}"));
+        #[cfg(unix)]
+        let result = std::os::unix::fs::symlink(target, link);
+        #[cfg(not(unix))]
+        let result = write(link, target.to_string_lossy().as_bytes());
+        if let Err(error) = result {
+            error!("Failed to link {name} for {tool_run}: {error}");
+        }
+    }
+
+    /// Records the end of a tool run in the [Diagnostics] returned by [Collector::diagnostics].
+    pub fn report_tool_run_result(&self, record: ToolRunRecord) {
+        lock_shared(&self.shared).diagnostics.tool_runs.push(record);
     }
 
     /// Reports the start of a tool's execution.
@@ -213,6 +431,17 @@ fn lock_shared<'m>(shared: &'m Mutex<Shared>) -> MutexGuard<'m, Shared> {
     }
 }
 
+/// Links to the stored copy of `_files` (whose hash is `hash`) in the diagnostics directory's
+/// object store from `link`. Symlinks are only supported on Unix, so elsewhere `_files` is copied
+/// to `link` instead.
+fn link_object(hash: ContentHash, _files: &RawDir, link: &Path) -> io::Result<()> {
+    let hash = hash.to_string();
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(Path::new("../..").join(OBJECTS_DIR_NAME).join(hash), link);
+    #[cfg(not(unix))]
+    _files.materialize(link)
+}
+
 /// Values shared by the Collector and various diagnostics handles. This is contained in an Option,
 /// which is set to `None` when [Collector::diagnostics] is called (and must remain Some() until
 /// then).
@@ -221,12 +450,25 @@ struct Shared {
     diagnostics: Diagnostics,
     // Path to the root of the diagnostics directory structure.
     diagnostics_dir: PathBuf,
+    // The content-addressed store in $diagnostics_dir/objects.
+    objects: ObjectStore,
+    // The content hash of each file tree stored by the previous report_ir_version call, keyed by
+    // its ID and the IR version that wrote it, so that trees that have not been rewritten are not
+    // hashed again. Trees without a provenance are always hashed.
+    object_hashes: HashMap<(Id, u64), ContentHash>,
     // Channel to send the Diagnostics to the Collector when this Shared is dropped.
     diagnostics_sender: Sender<Diagnostics>,
 
     // Writer for $diagnostic_dir/messages
     messages_file: SharedWriter<File>,
 
+    // The most recent IR version passed to report_ir_version, which the next version is diffed
+    // against.
+    previous_ir: Option<HarvestIR>,
+
+    // Representation kinds that report_ir_version can save.
+    representations: serialize::Registry,
+
     // The number of times each tool has been run. Tools that have not been run yet will not be
     // present in this map. This is incremented when a tool run starts, not when it ends.
     tool_run_counts: HashMap<ToolId, NonZeroU64>,
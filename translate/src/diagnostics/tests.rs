@@ -1,6 +1,7 @@
 use super::*;
 use crate::test_util::MockTool;
-use std::{fs::read_to_string, sync::mpsc::channel, thread::spawn};
+use std::fs::{read_link, read_to_string};
+use std::{sync::mpsc::channel, thread::spawn};
 
 /// Verifies that tracing messages are written into the correct files.
 #[test]
@@ -73,3 +74,209 @@ fn messages() {
     verify("steps/tool_b_001/messages", &["HHHH"]);
     verify("steps/tool_a_002/messages", &["EEEE", "GGGG", "IIII"]);
 }
+
+/// Verifies that tools can write files into their diagnostics directory.
+#[test]
+fn tool_run_file() {
+    let mut config = Config::mock();
+    let tempdir = tempdir().unwrap();
+    config.diagnostics_dir = Some(tempdir.path().to_path_buf());
+    let collector = Collector::initialize(&config).unwrap();
+    let (_joiner, tool_reporter) = collector
+        .reporter()
+        .start_tool_run(&MockTool::new())
+        .unwrap();
+    tool_reporter
+        .write_file("skipped", "a: too large\n")
+        .unwrap();
+    assert_eq!(
+        read_to_string(tempdir.path().join("steps/mock_tool_001/skipped")).unwrap(),
+        "a: too large\n"
+    );
+}
+
+/// Verifies that the IR index lists each representation's provenance.
+#[test]
+fn ir_index() {
+    use crate::tools::identify_project_kind::ProjectKind;
+    use harvest_ir::edit::Organizer;
+    let mut config = Config::mock();
+    let tempdir = tempdir().unwrap();
+    config.diagnostics_dir = Some(tempdir.path().to_path_buf());
+    let collector = Collector::initialize(&config).unwrap();
+    let mut organizer = Organizer::default();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    edit.set_stable_producer("loader".into());
+    let a = edit.add_representation(Box::new(ProjectKind::Library));
+    organizer.apply_edit(edit).unwrap();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    edit.set_producer("tool_a_001".into());
+    edit.set_stable_producer("tool_a".into());
+    edit.record_read(a);
+    let b = edit.add_representation(Box::new(ProjectKind::Executable));
+    organizer.apply_edit(edit).unwrap();
+    collector
+        .reporter()
+        .report_ir_version(organizer.version(), &organizer.snapshot());
+    let index = read_to_string(tempdir.path().join("ir/002/index")).unwrap();
+    let snapshot = organizer.snapshot();
+    let [key_a, key_b] = [a, b].map(|id| snapshot.stable_key(id).unwrap());
+    let [a, b] = [a, b].map(u64::from);
+    assert_eq!(
+        index,
+        format!(
+            "{a:03}: KindAndName [{key_a}] (by unknown producer in version 001)\n\
+             {b:03}: KindAndName [{key_b}] (by tool_a_001 in version 002, from {a:03})\n"
+        )
+    );
+}
+
+/// Verifies that saved IRs refer to file trees in the object store rather than containing them, so
+/// a file tree that is unchanged between versions is stored once, and that they can be resumed
+/// from.
+#[cfg(unix)]
+#[test]
+fn ir_objects() {
+    use crate::resume::load_ir;
+    use crate::tools::{identify_project_kind::ProjectKind, load_raw_source::RawSource};
+    use harvest_ir::{edit::Organizer, fs::RawDir};
+    let mut config = Config::mock();
+    let tempdir = tempdir().unwrap();
+    config.diagnostics_dir = Some(tempdir.path().to_path_buf());
+    let collector = Collector::initialize(&config).unwrap();
+    let reporter = collector.reporter();
+    let mut organizer = Organizer::default();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    let mut dir = RawDir::default();
+    dir.set_file("main.c", b"int main(void);\n".into()).unwrap();
+    let source = edit.add_representation(Box::new(RawSource { dir: dir.clone() }));
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    edit.add_representation(Box::new(ProjectKind::Executable));
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+
+    let objects = Vec::from_iter(
+        std::fs::read_dir(tempdir.path().join("objects"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name()),
+    );
+    assert_eq!(objects, [&*dir.content_hash().to_string()]);
+    for version in ["001", "002"] {
+        let saved = read_to_string(tempdir.path().join("ir").join(version).join(IR_FILE_NAME));
+        assert!(!saved.unwrap().contains("main.c"));
+    }
+    let (_, ir) = load_ir(tempdir.path()).unwrap();
+    let (id, loaded) = ir.get_one::<RawSource>().unwrap();
+    assert_eq!(id, source);
+    assert_eq!(
+        loaded.dir.get_file("main.c"),
+        Ok(b"int main(void);\n".as_slice())
+    );
+    assert_eq!(ir.get_by_representation::<ProjectKind>().count(), 1);
+}
+
+/// Verifies that reporting IR versions does not read lazily-loaded files into memory, and does not
+/// read file trees that were not rewritten since the previous version at all.
+#[cfg(unix)]
+#[test]
+fn lazy_ir_version() {
+    use crate::tools::{identify_project_kind::ProjectKind, load_raw_source::RawSource};
+    use harvest_ir::edit::Organizer;
+    use harvest_ir::fs::{PopulateOptions, RawDir};
+    let mut config = Config::mock();
+    let tempdir = tempdir().unwrap();
+    config.diagnostics_dir = Some(tempdir.path().join("diagnostics"));
+    let source = tempdir.path().join("source");
+    let mut dir = RawDir::default();
+    dir.set_file("main.c", b"int main(void);\n".into()).unwrap();
+    dir.materialize(&source).unwrap();
+    let options = PopulateOptions {
+        lazy: true,
+        ..Default::default()
+    };
+    let dir = RawDir::populate_with(std::fs::read_dir(&source).unwrap(), &options)
+        .unwrap()
+        .dir;
+    let collector = Collector::initialize(&config).unwrap();
+    let reporter = collector.reporter();
+    let mut organizer = Organizer::default();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    let id = edit.add_representation(Box::new(RawSource { dir: dir.clone() }));
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+    let hash = dir.content_hash();
+    // The source can no longer be read, so the next version must reuse the stored hash.
+    std::fs::remove_file(source.join("main.c")).unwrap();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    edit.add_representation(Box::new(ProjectKind::Executable));
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+    assert!(!dir.is_loaded());
+    let version = tempdir.path().join("diagnostics/ir/002");
+    assert!(
+        read_link(version.join(format!("{:03}", u64::from(id))))
+            .unwrap()
+            .ends_with(hash.to_string())
+    );
+    assert!(version.join(IR_FILE_NAME).exists());
+    let object = tempdir
+        .path()
+        .join("diagnostics/objects")
+        .join(hash.to_string());
+    assert_eq!(
+        read_to_string(object.join("main.c")).unwrap(),
+        "int main(void);\n"
+    );
+}
+
+/// Verifies that file trees are stored in the object store, and that file trees derived from an
+/// earlier IR version are written as a diff.
+#[cfg(unix)]
+#[test]
+fn ir_diff() {
+    use crate::tools::{load_raw_source::RawSource, raw_source_to_cargo_llm::CargoPackage};
+    use harvest_ir::{edit::Organizer, fs::RawDir};
+    let mut config = Config::mock();
+    let tempdir = tempdir().unwrap();
+    config.diagnostics_dir = Some(tempdir.path().to_path_buf());
+    let collector = Collector::initialize(&config).unwrap();
+    let reporter = collector.reporter();
+    let mut organizer = Organizer::default();
+    let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+    let mut dir = RawDir::default();
+    dir.set_file("main.c", b"int main() {\n    return 0;\n}\n".into())
+        .unwrap();
+    let source = edit.add_representation(Box::new(RawSource { dir }));
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+    let mut edit = organizer.new_edit(&[source].into(), &[].into()).unwrap();
+    let mut dir = RawDir::default();
+    dir.set_file("src/main.rs", b"fn main() {\n}\n".into())
+        .unwrap();
+    let hash = dir.content_hash();
+    let package = edit.add_representation(Box::new(CargoPackage { dir }));
+    edit.supersede(source, package);
+    organizer.apply_edit(edit).unwrap();
+    reporter.report_ir_version(organizer.version(), &organizer.snapshot());
+
+    let [source, package] = [source, package].map(|id| format!("{:03}", u64::from(id)));
+    let ir = tempdir.path().join("ir");
+    assert!(ir.join("001").join(&source).join("main.c").exists());
+    assert!(!ir.join("001/diff").exists());
+    assert_eq!(
+        read_link(ir.join("002").join(&package)).unwrap(),
+        PathBuf::from(format!("../../objects/{hash}"))
+    );
+    assert!(ir.join("002").join(&package).join("src/main.rs").exists());
+    assert_eq!(
+        read_to_string(ir.join("002/diff")).unwrap(),
+        format!(
+            "diff {source} {package}\n\
+             --- a/main.c\n+++ /dev/null\n@@ -1,3 +0,0 @@\n\
+             -int main() {{\n-    return 0;\n-}}\n\
+             --- /dev/null\n+++ b/src/main.rs\n@@ -0,0 +1,2 @@\n+fn main() {{\n+}}\n"
+        )
+    );
+}
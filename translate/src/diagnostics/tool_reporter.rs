@@ -23,6 +23,9 @@ use tracing_subscriber::{Layer as _, Registry};
 #[derive(Clone)]
 pub struct ToolReporter {
     run_shared: Arc<Mutex<RunShared>>,
+    tool_run: ToolRunId,
+    // This tool run's diagnostics directory (`steps/<tool_run>`).
+    tool_run_dir: PathBuf,
 }
 
 impl ToolReporter {
@@ -72,6 +75,8 @@ impl ToolReporter {
             ToolJoiner { receiver },
             ToolReporter {
                 run_shared: Arc::new(Mutex::new(RunShared { dispatch, sender })),
+                tool_run,
+                tool_run_dir,
             },
         ))
     }
@@ -86,6 +91,23 @@ impl ToolReporter {
         }
     }
 
+    /// Returns the ID of this tool run (e.g. `try_cargo_build_002`), which is also the name of its
+    /// diagnostics directory.
+    pub(crate) fn tool_run_id(&self) -> String {
+        self.tool_run.to_string()
+    }
+
+    /// Writes `contents` into the file `name` in this tool run's diagnostics directory
+    /// (`steps/<tool_run>/<name>`), replacing it if it exists.
+    pub fn write_file<C: AsRef<[u8]>>(&self, name: &str, contents: C) -> io::Result<()> {
+        std::fs::write(self.tool_run_dir.join(name), contents)
+    }
+
+    /// Returns the number of this tool run (1 for the first run of a particular tool, etc.).
+    pub(crate) fn run_number(&self) -> u64 {
+        self.tool_run.number.get()
+    }
+
     /// Utility to lock this reporter's shared reference.
     fn lock_shared(&self) -> MutexGuard<'_, RunShared> {
         match self.run_shared.lock() {
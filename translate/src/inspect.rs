@@ -0,0 +1,405 @@
+//! Inspection of the diagnostics directory written by a previous `translate` run. This is the
+//! implementation of HARVEST's `inspect` binary, which lists the IR versions and tool runs in a
+//! diagnostics directory, shows representations, and diffs IR versions.
+
+use clap::{Parser, Subcommand};
+use harvest_ir::fs::{
+    Change, Diff, FileContents, FileMetadata, GetFileError, Leaf, PopulateOptions, RawDir,
+};
+use std::collections::BTreeMap;
+use std::fs::{metadata, read, read_dir, read_link, read_to_string};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub use crate::diagnostics::{IndexEntry, IndexProvenance, ParseIndexError};
+
+/// Command-line arguments for HARVEST's `inspect` binary.
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Path to the diagnostics directory of a previous `translate` run.
+    pub diagnostics_dir: PathBuf,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// The `inspect` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Lists the IR versions, with the tool run that created each one.
+    Versions,
+    /// Lists the tool runs, with the IR versions they started from and created.
+    Runs,
+    /// Lists the representations in an IR version, or shows a single representation.
+    Show {
+        /// The IR version (e.g. `3` or `003`).
+        version: u64,
+        /// The ID of the representation to show. If omitted, the version's index is shown.
+        id: Option<u64>,
+        /// For file-tree representations, the path of a file within the tree to show. If omitted,
+        /// the tree's files are listed.
+        path: Option<PathBuf>,
+    },
+    /// Shows the representations added and removed between two IR versions, and the differences
+    /// in their contents.
+    Diff { old: u64, new: u64 },
+}
+
+/// Runs the `inspect` command described by `args`, writing its output to `out`.
+pub fn inspect<W: Write>(args: &Args, out: &mut W) -> Result<(), InspectError> {
+    let dir = DiagnosticsDir::new(&args.diagnostics_dir);
+    match args.command {
+        Command::Versions => {
+            let creators: BTreeMap<_, _> = dir
+                .tool_runs()?
+                .into_iter()
+                .filter_map(|run| Some((run.end?, run.name)))
+                .collect();
+            for version in dir.versions()? {
+                let count = dir.index(version)?.len();
+                let plural = if count == 1 { "" } else { "s" };
+                write!(out, "{version:03}: {count} representation{plural}")?;
+                match creators.get(&version) {
+                    Some(run) => writeln!(out, ", created by {run}")?,
+                    None if version == 0 => writeln!(out, ", initial IR")?,
+                    None => writeln!(out)?,
+                }
+            }
+        }
+        Command::Runs => {
+            for run in dir.tool_runs()? {
+                let version = |v: Option<u64>| v.map(|v| format!("{v:03}"));
+                let start = version(run.start).unwrap_or_else(|| "?".into());
+                let end = version(run.end).unwrap_or_else(|| "no new version".into());
+                writeln!(out, "{}: {start} -> {end}", run.name)?;
+            }
+        }
+        Command::Show {
+            version,
+            id: None,
+            path: _,
+        } => {
+            for entry in dir.index(version)? {
+                writeln!(out, "{entry}")?;
+            }
+        }
+        Command::Show {
+            version,
+            id: Some(id),
+            ref path,
+        } => match (dir.representation(version, id)?, path) {
+            (Loaded::File(contents), None) => out.write_all(&contents)?,
+            (Loaded::File(_), Some(_)) => return Err(InspectError::NotATree(id)),
+            (Loaded::Tree(tree), None) => {
+                for (path, contents) in tree.try_files_recursive()? {
+                    writeln!(out, "{} ({}B)", path.display(), contents.len())?;
+                }
+                for (path, target) in tree.symlinks_recursive() {
+                    writeln!(out, "{} -> {}", path.display(), target.display())?;
+                }
+            }
+            (Loaded::Tree(tree), Some(path)) => out.write_all(tree.get_file(path)?)?,
+        },
+        Command::Diff { old, new } => {
+            let (old_index, new_index) = (dir.index(old)?, dir.index(new)?);
+            let ids = |index: &[IndexEntry]| Vec::from_iter(index.iter().map(|e| e.id));
+            let (old_ids, new_ids) = (ids(&old_index), ids(&new_index));
+            for entry in old_index.iter().filter(|e| !new_ids.contains(&e.id)) {
+                writeln!(out, "- {entry}")?;
+            }
+            for entry in new_index.iter().filter(|e| !old_ids.contains(&e.id)) {
+                writeln!(out, "+ {entry}")?;
+            }
+            let mut all_ids = [old_ids, new_ids].concat();
+            all_ids.sort_unstable();
+            all_ids.dedup();
+            let mut diff = Diff::default();
+            for id in all_ids {
+                let load = |version, index: &[IndexEntry]| match index.iter().any(|e| e.id == id) {
+                    false => Ok(None),
+                    true => dir.representation(version, id).map(Some),
+                };
+                let old = load(old, &old_index)?;
+                let new = load(new, &new_index)?;
+                diff.changes
+                    .extend(changes(id, old.as_ref(), new.as_ref())?);
+            }
+            write!(out, "{diff}")?;
+        }
+    }
+    Ok(())
+}
+
+/// A diagnostics directory written by `translate`.
+pub struct DiagnosticsDir {
+    path: PathBuf,
+}
+
+/// A tool run, as recorded in the diagnostics directory's `steps` directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ToolRun {
+    /// The tool run ID, such as `try_cargo_build_001`.
+    pub name: String,
+    /// The IR version the tool was launched with.
+    pub start: Option<u64>,
+    /// The IR version created by applying the tool's edit, if it was applied.
+    pub end: Option<u64>,
+}
+
+/// The contents of a representation in an IR version's directory.
+enum Loaded {
+    /// A representation materialized as a single file.
+    File(FileContents),
+    /// A file-tree representation.
+    Tree(RawDir),
+}
+
+impl DiagnosticsDir {
+    pub fn new<P: Into<PathBuf>>(path: P) -> DiagnosticsDir {
+        DiagnosticsDir { path: path.into() }
+    }
+
+    /// Returns the IR versions in this directory, in order.
+    pub fn versions(&self) -> Result<Vec<u64>, InspectError> {
+        let mut versions = numbered_entries(&self.path.join("ir"))?;
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Returns the representations in IR version `version`, as listed in its index.
+    pub fn index(&self, version: u64) -> Result<Vec<IndexEntry>, InspectError> {
+        let path = self.version_dir(version)?.join("index");
+        read_to_string(path)?
+            .lines()
+            .map(|line| Ok(line.parse()?))
+            .collect()
+    }
+
+    /// Returns the tool runs in this directory, sorted by the IR version they started from.
+    pub fn tool_runs(&self) -> Result<Vec<ToolRun>, InspectError> {
+        let mut runs = vec![];
+        for entry in read_dir(self.path.join("steps"))? {
+            let entry = entry?;
+            let version = |name| linked_version(&entry.path().join(name));
+            runs.push(ToolRun {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                start: version("start_ir"),
+                end: version("end_ir"),
+            });
+        }
+        runs.sort_unstable_by(|a, b| (a.start, &a.name).cmp(&(b.start, &b.name)));
+        Ok(runs)
+    }
+
+    /// Returns the path to IR version `version`'s directory.
+    fn version_dir(&self, version: u64) -> Result<PathBuf, InspectError> {
+        let path = self.path.join("ir").join(format!("{version:03}"));
+        match path.is_dir() {
+            true => Ok(path),
+            false => Err(InspectError::UnknownVersion(version)),
+        }
+    }
+
+    /// Loads the representation `id` from IR version `version`'s directory. File trees are loaded
+    /// lazily, so only the files that are used are read.
+    fn representation(&self, version: u64, id: u64) -> Result<Loaded, InspectError> {
+        let path = self.version_dir(version)?.join(format!("{id:03}"));
+        let metadata = match metadata(&path) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Err(InspectError::UnknownRepresentation { version, id });
+            }
+            result => result?,
+        };
+        if !metadata.is_dir() {
+            return Ok(Loaded::File(read(path)?.into()));
+        }
+        let options = PopulateOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        Ok(Loaded::Tree(
+            RawDir::populate_with(read_dir(path)?, &options)?.dir,
+        ))
+    }
+}
+
+/// Returns the numbers of the entries in `dir` whose names are numbers (such as `001`).
+fn numbered_entries(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut numbers = vec![];
+    for entry in read_dir(dir)? {
+        if let Some(number) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            numbers.push(number);
+        }
+    }
+    Ok(numbers)
+}
+
+/// Returns the IR version that the `start_ir` or `end_ir` link at `link` points to. On platforms
+/// without symlinks, the link is a file containing its target.
+fn linked_version(link: &Path) -> Option<u64> {
+    let target = match read_link(link) {
+        Ok(target) => target,
+        Err(_) => read_to_string(link).ok()?.trim_end().into(),
+    };
+    target.file_name()?.to_str()?.parse().ok()
+}
+
+/// Returns the changes between two versions of the representation `id` (either of which may not
+/// exist). Paths are prefixed with the representation's ID, as in the IR version directories.
+fn changes(id: u64, old: Option<&Loaded>, new: Option<&Loaded>) -> io::Result<Vec<Change>> {
+    let prefix = PathBuf::from(format!("{id:03}"));
+    let file = |loaded: Option<&Loaded>| match loaded {
+        Some(Loaded::File(contents)) => Some(Leaf::File(contents.clone(), FileMetadata::default())),
+        _ => None,
+    };
+    let tree = |loaded: Option<&Loaded>| match loaded {
+        Some(Loaded::Tree(tree)) => Some(tree.clone()),
+        _ => None,
+    };
+    let mut changes = vec![];
+    let (old_file, new_file) = (file(old), file(new));
+    if old_file != new_file {
+        changes.push(Change {
+            path: prefix.clone(),
+            old: old_file,
+            new: new_file,
+        });
+    }
+    let (old_tree, new_tree) = (tree(old), tree(new));
+    if old_tree.is_some() || new_tree.is_some() {
+        let diff = old_tree
+            .unwrap_or_default()
+            .try_diff(&new_tree.unwrap_or_default())?;
+        changes.extend(diff.changes.into_iter().map(|change| Change {
+            path: prefix.join(&change.path),
+            ..change
+        }));
+    }
+    Ok(changes)
+}
+
+/// Error type returned by [inspect].
+#[derive(Debug, Error)]
+pub enum InspectError {
+    #[error("{0}")]
+    GetFile(#[from] GetFileError),
+    #[error("{0}")]
+    Index(#[from] ParseIndexError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("representation {0:03} is not a file tree")]
+    NotATree(u64),
+    #[error("IR version {version:03} has no representation {id:03}")]
+    UnknownRepresentation { version: u64, id: u64 },
+    #[error("IR version {0:03} does not exist")]
+    UnknownVersion(u64),
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+    use crate::cli::Config;
+    use crate::diagnostics::Collector;
+    use crate::test_util::{MockTool, tempdir};
+    use crate::tools::{identify_project_kind::ProjectKind, load_raw_source::RawSource};
+    use harvest_ir::edit::Organizer;
+
+    #[test]
+    fn inspect_test() {
+        let diagnostics = tempdir().unwrap();
+        let mut config = Config::mock();
+        config.diagnostics_dir = Some(diagnostics.path().to_path_buf());
+        let collector = Collector::initialize(&config).unwrap();
+        let reporter = collector.reporter();
+        let mut organizer = Organizer::default();
+        reporter.report_ir_version(0, &organizer.snapshot());
+        let (_joiner, tool_reporter) = reporter.start_tool_run(&MockTool::new()).unwrap();
+        let run = tool_reporter.tool_run_id();
+        reporter.report_tool_run_start(&run, 0);
+        let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+        edit.set_producer(run.clone());
+        let mut dir = RawDir::default();
+        dir.set_file("main.c", b"int main() {}\n".into()).unwrap();
+        let source = edit.add_representation(Box::new(RawSource { dir }));
+        let kind = edit.add_representation(Box::new(ProjectKind::Library));
+        organizer.apply_edit(edit).unwrap();
+        reporter.report_ir_version(1, &organizer.snapshot());
+        reporter.report_tool_run_end(&run, 1);
+        let mut edit = organizer
+            .new_edit(&[source, kind].into(), &[].into())
+            .unwrap();
+        let mut dir = RawDir::default();
+        dir.set_file("main.c", b"int main() { return 0; }\n".into())
+            .unwrap();
+        edit.write_id(source, Box::new(RawSource { dir }));
+        edit.remove_id(kind);
+        organizer.apply_edit(edit).unwrap();
+        reporter.report_ir_version(2, &organizer.snapshot());
+
+        let run_command = |command| {
+            let args = Args {
+                diagnostics_dir: diagnostics.path().to_path_buf(),
+                command,
+            };
+            let mut out = vec![];
+            inspect(&args, &mut out).map(|_| String::from_utf8(out).unwrap())
+        };
+        assert_eq!(
+            run_command(Command::Versions).unwrap(),
+            format!(
+                "000: 0 representations, initial IR\n\
+                 001: 2 representations, created by {run}\n\
+                 002: 1 representation\n"
+            )
+        );
+        assert_eq!(
+            run_command(Command::Runs).unwrap(),
+            format!("{run}: 000 -> 001\n")
+        );
+        let [source, kind] = [source, kind].map(u64::from);
+        let show = |version, id, path: Option<&str>| {
+            run_command(Command::Show {
+                version,
+                id,
+                path: path.map(Into::into),
+            })
+        };
+        assert_eq!(
+            show(1, None, None).unwrap(),
+            format!(
+                "{source:03}: RawSource (by {run} in version 001)\n\
+                 {kind:03}: KindAndName (by {run} in version 001)\n"
+            )
+        );
+        assert_eq!(show(1, Some(source), None).unwrap(), "main.c (14B)\n");
+        assert_eq!(
+            show(1, Some(source), Some("main.c")).unwrap(),
+            "int main() {}\n"
+        );
+        assert!(matches!(
+            show(1, Some(kind), Some("main.c")),
+            Err(InspectError::NotATree(_))
+        ));
+        assert!(matches!(
+            show(2, Some(kind), None),
+            Err(InspectError::UnknownRepresentation { .. })
+        ));
+        assert!(matches!(
+            show(3, None, None),
+            Err(InspectError::UnknownVersion(3))
+        ));
+        let diff = run_command(Command::Diff { old: 1, new: 2 }).unwrap();
+        assert!(
+            diff.starts_with(&format!(
+                "- {kind:03}: KindAndName (by {run} in version 001)\n"
+            )),
+            "{diff}"
+        );
+        assert!(
+            diff.contains("-int main() {}\n+int main() { return 0; }\n"),
+            "{diff}"
+        );
+        assert!(diff.contains("+++ /dev/null"), "{diff}");
+    }
+}
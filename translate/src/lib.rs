@@ -3,6 +3,8 @@
 
 pub mod cli;
 mod diagnostics;
+pub mod inspect;
+mod resume;
 mod runner;
 mod scheduler;
 pub mod tools;
@@ -11,53 +13,96 @@ pub mod util;
 #[cfg(test)]
 mod test_util;
 
-use crate::load_raw_source::LoadRawSource;
-use crate::tools::raw_source_to_cargo_llm::RawSourceToCargoLlm;
-use crate::tools::try_cargo_build::TryCargoBuild;
 use crate::tools::{MightWriteContext, MightWriteOutcome};
+pub use diagnostics::{Diagnostics, ToolRunRecord, ToolRunResult};
 use harvest_ir::HarvestIR;
 use harvest_ir::edit::{self, NewEditError};
 use runner::{SpawnToolError, ToolRunner};
+pub use scheduler::StalledTool;
 use scheduler::{NextInvocationOutcome, Scheduler};
 use std::sync::Arc;
-use tools::identify_project_kind::IdentifyProjectKind;
-use tools::load_raw_source;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 /// Performs the complete transpilation process using the scheduler.
-pub fn transpile(config: Arc<cli::Config>) -> Result<Arc<HarvestIR>, Box<dyn std::error::Error>> {
+///
+/// If `config.resume` is set, transpilation starts from the IR saved by a previous run rather
+/// than an empty IR, and tools whose outputs are already present in that IR are not run again
+/// (except for those named in `config.rerun`).
+///
+/// If the pipeline stalls, with tools still waiting for inputs that no remaining tool will
+/// produce, the returned outcome lists them (see [TranspileOutcome::stalled]).
+pub fn transpile(config: Arc<cli::Config>) -> Result<TranspileOutcome, Box<dyn std::error::Error>> {
     let collector = diagnostics::Collector::initialize(&config)?;
-    let mut ir_organizer = edit::Organizer::default();
-    let mut runner = ToolRunner::new(collector.reporter());
+    let resumed_ir = match &config.resume {
+        None => None,
+        Some(path) => {
+            let (version_dir, ir) = resume::load_ir(path)?;
+            info!("Resuming from {}", version_dir.display());
+            Some(ir)
+        }
+    };
+    // Record the starting IR (which is empty unless resuming) as version 0, so the diagnostics
+    // directory is complete and the first tool runs' `start_ir` links are valid.
+    let mut ir_organizer = edit::Organizer::with_harvest_ir(resumed_ir.unwrap_or_default());
+    collector
+        .reporter()
+        .report_ir_version(0, &ir_organizer.snapshot());
+    for invariant in tools::invariants() {
+        ir_organizer.add_invariant(invariant);
+    }
+    let mut runner = ToolRunner::new(collector.reporter(), config.strict_invariants);
     let mut scheduler = Scheduler::default();
-    scheduler.queue_invocation(LoadRawSource::new(&config.input));
-    scheduler.queue_invocation(IdentifyProjectKind);
-    scheduler.queue_invocation(RawSourceToCargoLlm);
-    scheduler.queue_invocation(TryCargoBuild);
+    let tools = tools::registry(&config).pipeline()?;
+    for name in &config.rerun {
+        if !tools.iter().any(|tool| tool.name() == name) {
+            warn!("--rerun: unknown tool {name}");
+        }
+    }
+    let initial_ir = ir_organizer.snapshot();
+    for tool in tools {
+        let name = tool.name();
+        if config.resume.is_some()
+            && !config.rerun.iter().any(|n| n == name)
+            && tool.already_ran(&initial_ir)
+        {
+            info!("Not queueing {name}, as its outputs are present in the resumed IR");
+            continue;
+        }
+        scheduler.queue_invocation(tool);
+    }
+    drop(initial_ir);
+    let pipeline = scheduler.dependency_graph();
+    debug!("Pipeline:\n{pipeline}");
+    collector.reporter().report_pipeline(&pipeline);
     loop {
         let snapshot = ir_organizer.snapshot();
-        scheduler.next_invocations(|mut tool| {
-            use NextInvocationOutcome::{DontTryAgain, Error, TryLater};
+        scheduler.next_invocations(&snapshot, |mut tool| {
+            use NextInvocationOutcome::{DontTryAgain, Error, TryLater, WaitForInputs};
             let name = tool.name();
-            let might_write = match tool.might_write(MightWriteContext { ir: &snapshot }) {
-                MightWriteOutcome::NotRunnable => {
-                    debug!("Tool {name} is not runnable");
-                    return DontTryAgain;
-                }
-                MightWriteOutcome::Runnable(might_write) => {
-                    debug!("Tool {name} is runnable");
-                    might_write
-                }
-                MightWriteOutcome::TryAgain => {
-                    debug!("Tool {name} returned TryAgain");
-                    return TryLater(tool);
-                }
-            };
+            let (might_write, might_read) =
+                match tool.might_write(MightWriteContext { ir: &snapshot }) {
+                    MightWriteOutcome::NotRunnable => {
+                        debug!("Tool {name} is not runnable");
+                        return DontTryAgain;
+                    }
+                    MightWriteOutcome::Runnable {
+                        might_write,
+                        might_read,
+                    } => {
+                        debug!("Tool {name} is runnable");
+                        (might_write, might_read)
+                    }
+                    MightWriteOutcome::TryAgain => {
+                        debug!("Tool {name} returned TryAgain");
+                        return WaitForInputs(tool);
+                    }
+                };
             match runner.spawn_tool(
                 &mut ir_organizer,
                 tool,
                 snapshot.clone(),
                 might_write,
+                might_read,
                 config.clone(),
             ) {
                 Err((SpawnToolError::IoError(error), _)) => {
@@ -78,15 +123,42 @@ pub fn transpile(config: Arc<cli::Config>) -> Result<Arc<HarvestIR>, Box<dyn std
                 }
             }
         })?;
-        if !runner.process_tool_results(&mut ir_organizer) {
+        if !runner.process_tool_results(&mut ir_organizer, &mut scheduler)? {
             // No tools are running now, which also indicates that no tools are schedulable.
-            // Eventually we need some way to determine whether this is a successful outcome or a
-            // failure, but for now we can just assume success.
             break;
         }
     }
+    let ir = ir_organizer.snapshot();
+    // Any tools left in the scheduler were not runnable with the final IR, and as nothing is
+    // running, the IR will not change.
+    let stalled = scheduler.stalled(&ir);
+    for tool in &stalled {
+        error!("Pipeline stalled: {tool}");
+    }
     drop(scheduler);
     drop(runner);
-    collector.diagnostics(); // TODO: Return this value (see issue 51)
-    Ok(ir_organizer.snapshot())
+    Ok(TranspileOutcome {
+        ir,
+        stalled,
+        diagnostics: collector.diagnostics(),
+    })
+}
+
+/// The result of a [transpile] run that did not fail outright.
+pub struct TranspileOutcome {
+    /// The final IR.
+    pub ir: Arc<HarvestIR>,
+    /// Tools that were still waiting to run when no tool was running, and so never ran. If this
+    /// is not empty, the pipeline stalled (e.g. because a tool did not produce a representation a
+    /// later tool needs) and `ir` is incomplete.
+    pub stalled: Vec<StalledTool>,
+    /// Diagnostics collected during the run, including a record of every tool run.
+    pub diagnostics: Diagnostics,
+}
+
+impl TranspileOutcome {
+    /// Returns true if the pipeline stalled (see [TranspileOutcome::stalled]).
+    pub fn is_stalled(&self) -> bool {
+        !self.stalled.is_empty()
+    }
 }
@@ -18,7 +18,11 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(()); // An early-exit argument was passed.
     };
     empty_writable_dir(&config.output, config.force).expect("output directory error");
-    let ir = transpile(config.into())?;
-    println!("{}", ir);
+    let outcome = transpile(config.into())?;
+    println!("{}", outcome.ir);
+    if outcome.is_stalled() {
+        let stalled: Vec<_> = outcome.stalled.iter().map(|t| t.to_string()).collect();
+        return Err(format!("pipeline stalled: {}", stalled.join(", ")).into());
+    }
     Ok(())
 }
@@ -0,0 +1,111 @@
+//! Support for resuming `transpile` from an IR version saved in a previous run's diagnostics
+//! directory.
+
+use crate::diagnostics::{IR_FILE_NAME, OBJECTS_DIR_NAME};
+use crate::tools;
+use harvest_ir::HarvestIR;
+use harvest_ir::fs::ObjectStore;
+use harvest_ir::serialize::LoadError;
+use std::fs::read_dir;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Loads the IR to resume from. `path` may either be a diagnostics directory, in which case the
+/// latest IR version in it is loaded, or a single IR version directory (`$diagnostics/ir/NNN`).
+pub fn load_ir(path: &Path) -> Result<(PathBuf, HarvestIR), ResumeError> {
+    let version_dir = match path.join(IR_FILE_NAME).exists() {
+        true => path.to_path_buf(),
+        false => latest_version(&path.join("ir"))?,
+    };
+    // Version directories are at $diagnostics/ir/NNN, and their file trees are in
+    // $diagnostics/objects.
+    let objects = ObjectStore::new(version_dir.join("../..").join(OBJECTS_DIR_NAME));
+    let ir = HarvestIR::load_with_objects(
+        version_dir.join(IR_FILE_NAME),
+        &tools::representations(),
+        &objects,
+    )?;
+    Ok((version_dir, ir))
+}
+
+/// Returns the path to the highest-numbered IR version in `ir_dir` that contains a saved IR.
+fn latest_version(ir_dir: &Path) -> Result<PathBuf, ResumeError> {
+    let mut latest = None;
+    for entry in read_dir(ir_dir)? {
+        let entry = entry?;
+        let Some(version) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if entry.path().join(IR_FILE_NAME).exists()
+            && latest.as_ref().is_none_or(|&(v, _)| version > v)
+        {
+            latest = Some((version, entry.path()));
+        }
+    }
+    latest
+        .map(|(_, path)| path)
+        .ok_or_else(|| ResumeError::NoIrVersions(ir_dir.to_path_buf()))
+}
+
+/// Error type returned by [load_ir].
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to load IR: {0}")]
+    Load(#[from] LoadError),
+    #[error("no saved IR versions found in {0}")]
+    NoIrVersions(PathBuf),
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+    use crate::tools::identify_project_kind::ProjectKind;
+    use std::fs::create_dir_all;
+
+    #[test]
+    fn load_ir_test() {
+        let diagnostics = tempdir().unwrap();
+        let save = |version: &str, kinds: &[ProjectKind]| {
+            let dir = diagnostics.path().join("ir").join(version);
+            create_dir_all(&dir).unwrap();
+            let mut ir = HarvestIR::default();
+            for kind in kinds {
+                ir.add_representation(Box::new(match kind {
+                    ProjectKind::Executable => ProjectKind::Executable,
+                    ProjectKind::Library => ProjectKind::Library,
+                }));
+            }
+            ir.save(dir.join(IR_FILE_NAME), &tools::representations())
+                .unwrap();
+            dir
+        };
+        assert!(matches!(
+            load_ir(diagnostics.path()),
+            Err(ResumeError::Io(_))
+        ));
+        create_dir_all(diagnostics.path().join("ir")).unwrap();
+        assert!(matches!(
+            load_ir(diagnostics.path()),
+            Err(ResumeError::NoIrVersions(_))
+        ));
+        let first = save("001", &[ProjectKind::Library]);
+        let second = save("002", &[ProjectKind::Library, ProjectKind::Executable]);
+        // A version directory without a saved IR should be ignored.
+        create_dir_all(diagnostics.path().join("ir/003")).unwrap();
+
+        let (path, ir) = load_ir(diagnostics.path()).unwrap();
+        assert_eq!(path, second);
+        assert_eq!(ir.get_by_representation::<ProjectKind>().count(), 2);
+        let (path, ir) = load_ir(&first).unwrap();
+        assert_eq!(path, first);
+        assert_eq!(ir.get_by_representation::<ProjectKind>().count(), 1);
+    }
+}
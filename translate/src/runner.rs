@@ -1,7 +1,10 @@
-use crate::diagnostics::Reporter;
+use crate::diagnostics::{Reporter, ToolRunRecord, ToolRunResult};
+use crate::scheduler::Scheduler;
 use crate::tools::{RunContext, Tool};
-use harvest_ir::edit::{self, NewEditError};
+use harvest_ir::edit::{self, ApplyEditError, NewEditError};
+use harvest_ir::invariant::Violation;
 use harvest_ir::{Edit, HarvestIR, Id};
+use std::any::Any;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io;
@@ -10,43 +13,55 @@ use std::panic::{AssertUnwindSafe, catch_unwind};
 use std::sync::Arc;
 use std::sync::mpsc::{Receiver, Sender, channel};
 use std::thread::{self, JoinHandle, ThreadId, spawn};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, info};
 
 /// Spawns off each tool execution in its own thread, and keeps track of those threads.
 pub struct ToolRunner {
     invocations: HashMap<ThreadId, RunningInvocation>,
 
     // Diagnostic fields.
-    // IR version number. The version start at 0 and increments by 1 every time an IR edit is
-    // successfully applied.
-    ir_version: u64,
     reporter: Reporter,
 
+    // If true, an invariant violation causes `process_tool_results` to return an error.
+    strict_invariants: bool,
+
     // Channel used by threads to signal that they are completed running.
     receiver: Receiver<ThreadId>,
     sender: Sender<ThreadId>,
 }
 
 impl ToolRunner {
-    /// Creates a new ToolRunner.
-    pub fn new(reporter: Reporter) -> ToolRunner {
+    /// Creates a new ToolRunner. If `strict_invariants` is true, `process_tool_results` fails if
+    /// an edit violates an IR invariant.
+    pub fn new(reporter: Reporter, strict_invariants: bool) -> ToolRunner {
         let (sender, receiver) = channel();
         ToolRunner {
             invocations: HashMap::new(),
-            ir_version: 0,
             reporter,
+            strict_invariants,
             receiver,
             sender,
         }
     }
 
     /// Waits until at least one tool has completed running, then process the results of all
-    /// completed tool invocations. This will update the IR value in edit_organizer. Returns `true`
-    /// if at least one tool completed, and `false` if no tools are currently running.
-    pub fn process_tool_results(&mut self, edit_organizer: &mut edit::Organizer) -> bool {
+    /// completed tool invocations. This will update the IR value in edit_organizer. The tool
+    /// invocations suggested by tools whose edits are applied are queued in `scheduler`. If a
+    /// tool's edit is rejected as stale, the tool's retry invocation (if it has one) is queued in
+    /// `scheduler` instead. Returns `true` if at least one tool completed, and `false` if no tools
+    /// are currently running.
+    ///
+    /// The IR's invariants are checked after every applied edit. Violations are reported to the
+    /// diagnostics, and in strict mode an error is returned.
+    pub fn process_tool_results(
+        &mut self,
+        edit_organizer: &mut edit::Organizer,
+        scheduler: &mut Scheduler,
+    ) -> Result<bool, InvariantsViolated> {
         if self.invocations.is_empty() {
-            return false;
+            return Ok(false);
         }
         for thread_id in
             once(self.receiver.recv().expect("sender dropped")).chain(self.receiver.try_iter())
@@ -55,22 +70,70 @@ impl ToolRunner {
                 .invocations
                 .remove(&thread_id)
                 .expect("missing invocation");
-            let completed_invocation = invocation
+            let (completed_invocation, duration) = invocation
                 .join_handle
                 .join()
                 .expect("tool invocation thread panicked");
-            let Ok(edit) = completed_invocation else {
-                continue;
+            let record = |result, end_version| ToolRunRecord {
+                name: invocation.name,
+                number: invocation.number,
+                duration,
+                result,
+                start_version: invocation.start_version,
+                end_version,
+            };
+            let (edit, suggestions) = match completed_invocation {
+                Err(result) => {
+                    self.reporter.report_tool_run_result(record(result, None));
+                    continue;
+                }
+                Ok(output) => output,
             };
-            if let Err(error) = edit_organizer.apply_edit(edit) {
-                error!("Edit application error: {error:?}");
+            match (edit_organizer.apply_edit(edit), invocation.retry) {
+                (Ok(()), _) => {}
+                (Err(error @ ApplyEditError::StaleEdit(id)), Some(retry)) => {
+                    info!(
+                        "{} read {id}, which changed while it ran; retrying",
+                        retry.name()
+                    );
+                    let result = ToolRunResult::EditRejected(error.to_string());
+                    self.reporter.report_tool_run_result(record(result, None));
+                    scheduler.queue_invocation(retry);
+                    continue;
+                }
+                (Err(error), _) => {
+                    error!("Edit application error: {error:?}");
+                    let result = ToolRunResult::EditRejected(error.to_string());
+                    self.reporter.report_tool_run_result(record(result, None));
+                    continue;
+                }
+            }
+            let version = edit_organizer.version();
+            self.reporter
+                .report_ir_version(version, &edit_organizer.snapshot());
+            self.reporter
+                .report_tool_run_end(&invocation.tool_run, version);
+            self.reporter
+                .report_tool_run_result(record(ToolRunResult::Succeeded, Some(version)));
+            for suggestion in suggestions {
+                info!("{} suggested {}", invocation.tool_run, suggestion.name());
+                scheduler.queue_invocation(suggestion);
+            }
+            let violations = edit_organizer.check_invariants();
+            if violations.is_empty() {
                 continue;
             }
-            self.ir_version += 1;
             self.reporter
-                .report_ir_version(self.ir_version, &edit_organizer.snapshot());
+                .report_invariant_violations(version, &invocation.tool_run, &violations);
+            if self.strict_invariants {
+                return Err(InvariantsViolated {
+                    tool_run: invocation.tool_run,
+                    version,
+                    violations,
+                });
+            }
         }
-        true
+        Ok(true)
     }
 
     /// Runs a tool. The tool is run in a new thread.
@@ -80,9 +143,10 @@ impl ToolRunner {
         tool: Box<dyn Tool>,
         ir_snapshot: Arc<HarvestIR>,
         might_write: HashSet<Id>,
+        might_read: HashSet<Id>,
         config: Arc<crate::cli::Config>,
     ) -> Result<(), (SpawnToolError, Box<dyn Tool>)> {
-        let mut edit = match edit_organizer.new_edit(&might_write) {
+        let mut edit = match edit_organizer.new_edit(&might_write, &might_read) {
             Err(error) => return Err((error.into(), tool)),
             Ok(edit) => edit,
         };
@@ -91,8 +155,18 @@ impl ToolRunner {
             Err(error) => return Err((error.into(), tool)),
             Ok(joiner_reporter) => joiner_reporter,
         };
+        let tool_run = tool_reporter.tool_run_id();
+        let number = tool_reporter.run_number();
+        let name = tool.name();
+        let start_version = edit_organizer.version();
+        self.reporter
+            .report_tool_run_start(&tool_run, start_version);
+        edit.set_producer(tool_run.clone());
+        edit.set_stable_producer(tool.name().into());
+        let retry = tool.retry();
         let join_handle = spawn(move || {
             let logger = tool_reporter.setup_thread_logger();
+            let start = Instant::now();
             // Tool::run is not necessarily unwind safe, which means that if it panics it might
             // leave shared data in a state that violates invariants. Types that are shared between
             // threads can generally handle this (e.g. Mutex and RwLock have poisoning), but
@@ -101,32 +175,45 @@ impl ToolRunner {
             // same thread* that `tool` might touch are appropriately dropped/forgotten if `run`
             // panics.
             let result = catch_unwind(AssertUnwindSafe(|| {
+                let mut suggestions = vec![];
                 tool.run(RunContext {
                     ir_edit: &mut edit,
                     ir_snapshot,
                     config,
                     reporter: tool_reporter,
+                    suggestions: &mut suggestions,
                 })
-                .map(|_| edit)
+                .map(|_| (edit, suggestions))
             }));
-            // TODO: Diagnostics module.
+            let duration = start.elapsed();
             let out = match result {
                 Err(panic_error) => {
-                    error!("Tool panicked: {panic_error:?}");
-                    Err(())
+                    let payload = panic_payload(&*panic_error);
+                    let message = payload.as_deref().unwrap_or("(non-string payload)");
+                    error!("Tool panicked: {message}");
+                    Err(ToolRunResult::Panicked(payload))
                 }
                 Ok(Err(tool_error)) => {
                     error!("Tool invocation failed: {tool_error}");
-                    Err(())
+                    Err(ToolRunResult::Failed(tool_error.to_string()))
                 }
-                Ok(Ok(edit)) => Ok(edit),
+                Ok(Ok(output)) => Ok(output),
             };
             tool_joiner.join(logger);
             let _ = sender.send(thread::current().id());
-            out
+            (out, duration)
         });
-        self.invocations
-            .insert(join_handle.thread().id(), RunningInvocation { join_handle });
+        self.invocations.insert(
+            join_handle.thread().id(),
+            RunningInvocation {
+                join_handle,
+                name,
+                number,
+                retry,
+                start_version,
+                tool_run,
+            },
+        );
         Ok(())
     }
 }
@@ -140,10 +227,49 @@ pub enum SpawnToolError {
     NewEdit(#[from] NewEditError),
 }
 
+/// Error returned by `process_tool_results` in strict mode when a tool's edit violates IR
+/// invariants.
+#[derive(Debug, Error)]
+#[error(
+    "{tool_run} violated IR invariants (IR version {version}): {}",
+    join(violations)
+)]
+pub struct InvariantsViolated {
+    pub tool_run: String,
+    pub version: u64,
+    pub violations: Vec<Violation>,
+}
+
+/// Formats `violations` as a semicolon-separated list.
+fn join(violations: &[Violation]) -> String {
+    Vec::from_iter(violations.iter().map(Violation::to_string)).join("; ")
+}
+
+/// Returns the message a panic was started with, if it was a string.
+fn panic_payload(payload: &(dyn Any + Send)) -> Option<String> {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => Some(message.to_string()),
+        None => payload.downcast_ref::<String>().cloned(),
+    }
+}
+
+/// The value returned by a tool run's thread: the tool's edit and suggested invocations (or how it
+/// failed), and how long the tool ran for.
+type ThreadResult = (Result<(Edit, Vec<Box<dyn Tool>>), ToolRunResult>, Duration);
+
 /// Data the ToolRunner tracks for each currently-running thread. These are accessed from the main
 /// thread.
 struct RunningInvocation {
-    join_handle: JoinHandle<Result<Edit, ()>>,
+    join_handle: JoinHandle<ThreadResult>,
+    // The tool's name and run number, for diagnostics.
+    name: &'static str,
+    number: u64,
+    // Invocation to queue if this invocation's edit is stale.
+    retry: Option<Box<dyn Tool>>,
+    // The IR version the tool was launched with.
+    start_version: u64,
+    // The tool run's ID, for diagnostics.
+    tool_run: String,
 }
 
 #[cfg(all(test, not(miri)))]
@@ -173,20 +299,24 @@ mod tests {
     fn new_edit_errors() {
         let collector = Collector::initialize(&Config::mock()).unwrap();
         let mut edit_organizer = edit::Organizer::default();
-        let mut edit = edit_organizer.new_edit(&[].into()).unwrap();
+        let mut edit = edit_organizer.new_edit(&[].into(), &[].into()).unwrap();
         let config = Arc::new(crate::cli::Config::mock());
         let [a, b, c] = [(); 3].map(|_| edit.add_representation(Box::new(TestRepresentation)));
         edit_organizer.apply_edit(edit).expect("setup edit failed");
-        let mut runner = ToolRunner::new(collector.reporter());
+        let mut runner = ToolRunner::new(collector.reporter(), false);
         let unknown_id = Id::new();
         let snapshot = edit_organizer.snapshot();
         let result = runner.spawn_tool(
             &mut edit_organizer,
             MockTool::new()
-                .might_write(move |_| Runnable([a, unknown_id].into()))
+                .might_write(move |_| Runnable {
+                    might_write: [a, unknown_id].into(),
+                    might_read: [].into(),
+                })
                 .boxed(),
             snapshot.clone(),
             [a, unknown_id].into(),
+            [].into(),
             config.clone(),
         );
         assert!(matches!(
@@ -197,21 +327,29 @@ mod tests {
         let result = runner.spawn_tool(
             &mut edit_organizer,
             MockTool::new()
-                .might_write(move |_| Runnable([b, c].into()))
+                .might_write(move |_| Runnable {
+                    might_write: [b, c].into(),
+                    might_read: [].into(),
+                })
                 .run(move |_| receiver.recv().map_err(Into::into))
                 .boxed(),
             snapshot.clone(),
             [a, b].into(),
+            [].into(),
             config.clone(),
         );
         assert!(result.is_ok());
         let result = runner.spawn_tool(
             &mut edit_organizer,
             MockTool::new()
-                .might_write(move |_| Runnable([b, c].into()))
+                .might_write(move |_| Runnable {
+                    might_write: [b, c].into(),
+                    might_read: [].into(),
+                })
                 .boxed(),
             snapshot,
             [b, c].into(),
+            [].into(),
             config.clone(),
         );
         assert!(
@@ -222,24 +360,29 @@ mod tests {
             "spawned tool with in-use ID"
         );
         sender.send(()).expect("receiver dropped");
-        runner.process_tool_results(&mut edit_organizer);
+        runner
+            .process_tool_results(&mut edit_organizer, &mut Scheduler::default())
+            .unwrap();
     }
 
     #[test]
     fn replaced_edit() {
         let collector = Collector::initialize(&Config::mock()).unwrap();
         let mut edit_organizer = edit::Organizer::default();
-        let mut edit = edit_organizer.new_edit(&[].into()).unwrap();
+        let mut edit = edit_organizer.new_edit(&[].into(), &[].into()).unwrap();
         let a = edit.add_representation(Box::new(TestRepresentation));
         edit_organizer.apply_edit(edit).expect("setup edit failed");
-        let mut runner = ToolRunner::new(collector.reporter());
+        let mut runner = ToolRunner::new(collector.reporter(), false);
         let (sender, receiver) = channel();
         let snapshot = edit_organizer.snapshot();
         let config = Arc::new(crate::cli::Config::mock());
         let result = runner.spawn_tool(
             &mut edit_organizer,
             MockTool::new()
-                .might_write(move |_| Runnable([a].into()))
+                .might_write(move |_| Runnable {
+                    might_write: [a].into(),
+                    might_read: [].into(),
+                })
                 .run(move |c| {
                     *c.ir_edit = receiver.recv()?;
                     Ok(())
@@ -247,15 +390,20 @@ mod tests {
                 .boxed(),
             snapshot,
             [a].into(),
+            [].into(),
             config.clone(),
         );
         assert!(result.is_ok());
         // Verify that `a` was marked as in use
-        assert!(edit_organizer.new_edit(&[a].into()).err() == Some(NewEditError::IdInUse));
-        let mut edit = edit_organizer.new_edit(&[].into()).unwrap();
+        assert!(
+            edit_organizer.new_edit(&[a].into(), &[].into()).err() == Some(NewEditError::IdInUse)
+        );
+        let mut edit = edit_organizer.new_edit(&[].into(), &[].into()).unwrap();
         let b = edit.add_representation(Box::new(TestRepresentation));
         sender.send(edit).expect("receiver dropped");
-        runner.process_tool_results(&mut edit_organizer);
+        runner
+            .process_tool_results(&mut edit_organizer, &mut Scheduler::default())
+            .unwrap();
         let ir_ids: Vec<Id> = edit_organizer.snapshot().iter().map(|(id, _)| id).collect();
         // We don't really need this *exact* behavior, but we do need to verify the runner does
         // something reasonable.
@@ -266,7 +414,7 @@ mod tests {
     fn success() {
         let collector = Collector::initialize(&Config::mock()).unwrap();
         let mut edit_organizer = edit::Organizer::default();
-        let mut runner = ToolRunner::new(collector.reporter());
+        let mut runner = ToolRunner::new(collector.reporter(), false);
         let snapshot = edit_organizer.snapshot();
         let config = Arc::new(crate::cli::Config::mock());
         let result = runner.spawn_tool(
@@ -279,21 +427,33 @@ mod tests {
                 .boxed(),
             snapshot,
             [].into(),
+            [].into(),
             config.clone(),
         );
         assert!(result.is_ok());
         let ir_count = edit_organizer.snapshot().iter().count();
         assert_eq!(ir_count, 0, "edit applied early");
-        runner.process_tool_results(&mut edit_organizer);
+        runner
+            .process_tool_results(&mut edit_organizer, &mut Scheduler::default())
+            .unwrap();
         let ir_count = edit_organizer.snapshot().iter().count();
         assert_eq!(ir_count, 1, "edit not applied on success");
+        drop(runner);
+        let tool_runs = collector.diagnostics().tool_runs;
+        assert_eq!(tool_runs.len(), 1);
+        assert_eq!(tool_runs[0].tool_run_id(), "mock_tool_001");
+        assert_eq!(tool_runs[0].result, ToolRunResult::Succeeded);
+        assert_eq!(
+            (tool_runs[0].start_version, tool_runs[0].end_version),
+            (0, Some(1))
+        );
     }
 
     #[test]
     fn tool_error() {
         let collector = Collector::initialize(&Config::mock()).unwrap();
         let mut edit_organizer = edit::Organizer::default();
-        let mut runner = ToolRunner::new(collector.reporter());
+        let mut runner = ToolRunner::new(collector.reporter(), false);
         let snapshot = edit_organizer.snapshot();
         let config = Arc::new(crate::cli::Config::mock());
         let result = runner.spawn_tool(
@@ -301,19 +461,29 @@ mod tests {
             MockTool::new().run(|_| Err("test error".into())).boxed(),
             snapshot,
             [].into(),
+            [].into(),
             config.clone(),
         );
         assert!(result.is_ok());
-        runner.process_tool_results(&mut edit_organizer);
+        runner
+            .process_tool_results(&mut edit_organizer, &mut Scheduler::default())
+            .unwrap();
         let ir_count = edit_organizer.snapshot().iter().count();
         assert_eq!(ir_count, 0, "edit applied when tool errored");
+        drop(runner);
+        let tool_runs = collector.diagnostics().tool_runs;
+        assert_eq!(
+            tool_runs[0].result,
+            ToolRunResult::Failed("test error".into())
+        );
+        assert_eq!(tool_runs[0].end_version, None);
     }
 
     #[test]
     fn tool_panic() {
         let collector = Collector::initialize(&Config::mock()).unwrap();
         let mut edit_organizer = edit::Organizer::default();
-        let mut runner = ToolRunner::new(collector.reporter());
+        let mut runner = ToolRunner::new(collector.reporter(), false);
         let snapshot = edit_organizer.snapshot();
         let config = Arc::new(crate::cli::Config::mock());
         let result = runner.spawn_tool(
@@ -321,11 +491,151 @@ mod tests {
             MockTool::new().run(|_| panic!("test panic")).boxed(),
             snapshot,
             [].into(),
+            [].into(),
             config.clone(),
         );
         assert!(result.is_ok());
-        runner.process_tool_results(&mut edit_organizer);
+        runner
+            .process_tool_results(&mut edit_organizer, &mut Scheduler::default())
+            .unwrap();
         let ir_count = edit_organizer.snapshot().iter().count();
         assert_eq!(ir_count, 0, "edit applied when tool panicked");
+        drop(runner);
+        let tool_runs = collector.diagnostics().tool_runs;
+        assert_eq!(
+            tool_runs[0].result,
+            ToolRunResult::Panicked(Some("test panic".into()))
+        );
+    }
+
+    #[test]
+    fn stale_edit_retry() {
+        let collector = Collector::initialize(&Config::mock()).unwrap();
+        let mut edit_organizer = edit::Organizer::default();
+        let mut edit = edit_organizer.new_edit(&[].into(), &[].into()).unwrap();
+        let a = edit.add_representation(Box::new(TestRepresentation));
+        edit_organizer.apply_edit(edit).expect("setup edit failed");
+        let mut runner = ToolRunner::new(collector.reporter(), false);
+        let mut scheduler = Scheduler::default();
+        let (sender, receiver) = channel();
+        let snapshot = edit_organizer.snapshot();
+        let config = Arc::new(crate::cli::Config::mock());
+        let result = runner.spawn_tool(
+            &mut edit_organizer,
+            MockTool::new()
+                .retry(|| Some(MockTool::new().name("retry").boxed()))
+                .run(move |c| {
+                    receiver.recv()?;
+                    c.ir_edit.add_representation(Box::new(TestRepresentation));
+                    Ok(())
+                })
+                .boxed(),
+            snapshot,
+            [].into(),
+            [a].into(),
+            config.clone(),
+        );
+        assert!(result.is_ok());
+        // Modify `a` while the tool is running.
+        let mut edit = edit_organizer.new_edit(&[a].into(), &[].into()).unwrap();
+        edit.write_id(a, Box::new(TestRepresentation));
+        edit_organizer
+            .apply_edit(edit)
+            .expect("modifying edit failed");
+        sender.send(()).expect("receiver dropped");
+        runner
+            .process_tool_results(&mut edit_organizer, &mut scheduler)
+            .unwrap();
+        let ir_count = edit_organizer.snapshot().iter().count();
+        assert_eq!(ir_count, 1, "stale edit applied");
+        let mut queued = vec![];
+        scheduler
+            .next_invocations(&HarvestIR::default(), |t| {
+                queued.push(t.name());
+                crate::scheduler::NextInvocationOutcome::DontTryAgain
+            })
+            .unwrap();
+        assert_eq!(queued, ["retry"]);
+        drop(runner);
+        assert!(matches!(
+            collector.diagnostics().tool_runs[0].result,
+            ToolRunResult::EditRejected(_)
+        ));
+    }
+
+    #[test]
+    fn strict_invariants() {
+        use harvest_ir::invariant::AtMostOne;
+        let collector = Collector::initialize(&Config::mock()).unwrap();
+        let config = Arc::new(crate::cli::Config::mock());
+        for strict in [false, true] {
+            let mut edit_organizer = edit::Organizer::default();
+            edit_organizer.add_invariant(Box::new(AtMostOne::<TestRepresentation>::new()));
+            let mut runner = ToolRunner::new(collector.reporter(), strict);
+            let snapshot = edit_organizer.snapshot();
+            let result = runner.spawn_tool(
+                &mut edit_organizer,
+                MockTool::new()
+                    .run(|c| {
+                        c.ir_edit.add_representation(Box::new(TestRepresentation));
+                        c.ir_edit.add_representation(Box::new(TestRepresentation));
+                        Ok(())
+                    })
+                    .boxed(),
+                snapshot,
+                [].into(),
+                [].into(),
+                config.clone(),
+            );
+            assert!(result.is_ok());
+            let result =
+                runner.process_tool_results(&mut edit_organizer, &mut Scheduler::default());
+            match strict {
+                false => assert!(matches!(result, Ok(true))),
+                true => {
+                    let error = result.expect_err("strict mode ignored violation");
+                    assert_eq!(error.tool_run, "mock_tool_002");
+                    assert_eq!(error.version, 1);
+                    assert_eq!(error.violations.len(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn suggest() {
+        let collector = Collector::initialize(&Config::mock()).unwrap();
+        let mut edit_organizer = edit::Organizer::default();
+        let mut runner = ToolRunner::new(collector.reporter(), false);
+        let mut scheduler = Scheduler::default();
+        let config = Arc::new(crate::cli::Config::mock());
+        for (name, result) in [("succeeds", Ok(())), ("fails", Err("test error"))] {
+            let result = runner.spawn_tool(
+                &mut edit_organizer,
+                MockTool::new()
+                    .run(move |mut c| {
+                        c.suggest(MockTool::new().name(name).boxed());
+                        Ok(result?)
+                    })
+                    .boxed(),
+                HarvestIR::default().into(),
+                [].into(),
+                [].into(),
+                config.clone(),
+            );
+            assert!(result.is_ok());
+            runner
+                .process_tool_results(&mut edit_organizer, &mut scheduler)
+                .unwrap();
+        }
+        // Only the successful tool's suggestion is queued.
+        let mut queued = vec![];
+        scheduler
+            .next_invocations(&HarvestIR::default(), |t| {
+                queued.push(t.name());
+                crate::scheduler::NextInvocationOutcome::DontTryAgain
+            })
+            .unwrap();
+        assert_eq!(queued, ["succeeds"]);
     }
 }
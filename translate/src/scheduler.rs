@@ -2,27 +2,59 @@
 //!
 //! The scheduler is responsible for determining which tools to invoke and also
 //! for invoking them.
+//!
+//! Tools that are waiting for their inputs (see [NextInvocationOutcome::WaitForInputs]) are set
+//! aside until a representation of a type they consume (see [Tool::consumes]) appears in the IR or
+//! is rewritten, rather than being asked whether they are runnable on every iteration.
 
-use crate::tools::Tool;
-use std::mem::replace;
+use crate::tools::{RepresentationType, Tool};
+use harvest_ir::{HarvestIR, Id};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::mem::{replace, take};
 use tracing::debug;
 
 #[derive(Default)]
 pub struct Scheduler {
     queued_invocations: Vec<Box<dyn Tool>>,
+    waiting: Vec<Waiting>,
+}
+
+/// A tool invocation that is waiting for new inputs.
+struct Waiting {
+    tool: Box<dyn Tool>,
+    /// The representations of the types `tool` consumes that were present when it started
+    /// waiting (see [consumed_versions]).
+    seen: BTreeMap<Id, Option<u64>>,
 }
 
 impl Scheduler {
     /// Invokes `f` with the next suggested tool invocations. `f` is expected to try to run each
     /// tool. If the tool cannot be executed and should be tried again later, then `f` should
     /// return it.
+    ///
+    /// `ir` should be the current IR; waiting tools whose inputs have changed in it are invoked
+    /// along with the queued tools.
     pub fn next_invocations<F: FnMut(Box<dyn Tool>) -> NextInvocationOutcome>(
         &mut self,
+        ir: &HarvestIR,
         mut f: F,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        for waiting in take(&mut self.waiting) {
+            let current = consumed_versions(&*waiting.tool, ir);
+            if current
+                .iter()
+                .all(|(id, version)| waiting.seen.get(id) == Some(version))
+            {
+                self.waiting.push(waiting);
+            } else {
+                debug!("New inputs for {}, waking it", waiting.tool.name());
+                self.queued_invocations.push(waiting.tool);
+            }
+        }
         let new_queue = Vec::with_capacity(self.queued_invocations.len());
         for tool in replace(&mut self.queued_invocations, new_queue) {
-            use NextInvocationOutcome::{DontTryAgain, Error, TryLater};
+            use NextInvocationOutcome::{DontTryAgain, Error, TryLater, WaitForInputs};
             debug!("Trying to invoke tool {}", tool.name());
             match f(tool) {
                 DontTryAgain => debug!("Tool removed from queue"),
@@ -30,17 +62,80 @@ impl Scheduler {
                     debug!("Returning {} to queue", tool.name());
                     self.queued_invocations.push(tool);
                 }
+                // A tool that does not declare its inputs cannot be woken, so it is polled.
+                WaitForInputs(tool) if tool.consumes().is_empty() => {
+                    debug!("Returning {} to queue", tool.name());
+                    self.queued_invocations.push(tool);
+                }
+                WaitForInputs(tool) => {
+                    debug!("{} is waiting for new inputs", tool.name());
+                    let seen = consumed_versions(&*tool, ir);
+                    self.waiting.push(Waiting { tool, seen });
+                }
                 Error(error) => return Err(error),
             }
         }
         Ok(())
     }
 
+    /// Returns the dependency graph between the tools that are queued or waiting.
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        let tools = self
+            .queued_invocations
+            .iter()
+            .chain(self.waiting.iter().map(|waiting| &waiting.tool));
+        DependencyGraph {
+            tools: tools
+                .map(|tool| (tool.name(), tool.consumes(), tool.produces()))
+                .collect(),
+        }
+    }
+
     /// Add a tool invocation to the scheduler's queue. Note that scheduling a
     /// tool invocation does not guarantee the tool will run, as a tool may
     /// indicate that it is not runnable.
-    pub fn queue_invocation<T: Tool>(&mut self, invocation: T) {
-        self.queued_invocations.push(Box::new(invocation));
+    pub fn queue_invocation(&mut self, invocation: Box<dyn Tool>) {
+        self.queued_invocations.push(invocation);
+    }
+
+    /// Returns the tool invocations that are still queued or waiting. Once no tools are running,
+    /// these will never become runnable, so the pipeline has stalled.
+    pub fn stalled(&self, ir: &HarvestIR) -> Vec<StalledTool> {
+        self.queued_invocations
+            .iter()
+            .chain(self.waiting.iter().map(|waiting| &waiting.tool))
+            .map(|tool| StalledTool {
+                name: tool.name(),
+                missing: tool
+                    .consumes()
+                    .into_iter()
+                    .filter(|ty| ty.ids(ir).is_empty())
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// A tool invocation that never ran because the pipeline stalled (see [Scheduler::stalled]).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StalledTool {
+    /// The tool's name.
+    pub name: &'static str,
+    /// The representation types the tool consumes that are not in the IR.
+    pub missing: Vec<RepresentationType>,
+}
+
+impl Display for StalledTool {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.missing.is_empty() {
+            return write!(f, "{} (not runnable with the final IR)", self.name);
+        }
+        write!(f, "{} (waiting for ", self.name)?;
+        for (i, ty) in self.missing.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(f, "{separator}{ty}")?;
+        }
+        f.write_str(")")
     }
 }
 
@@ -51,24 +146,129 @@ pub enum NextInvocationOutcome {
     /// Indicates this tool invocation should be tried again later, after other tool invocations
     /// have completed.
     TryLater(Box<dyn Tool>),
+    /// Indicates this tool invocation should be tried again once a representation of a type it
+    /// consumes is added to the IR or rewritten.
+    WaitForInputs(Box<dyn Tool>),
     /// Reports an error that `next_invocations` should immediately return.
     Error(Box<dyn std::error::Error>),
 }
 
+/// Returns the IDs of all representations in `ir` of the types `tool` consumes, with the IR
+/// version that last wrote each of them (from its [Provenance](harvest_ir::Provenance), if it has
+/// one). Comparing these detects representations that were rewritten in place, as well as new
+/// ones.
+fn consumed_versions(tool: &dyn Tool, ir: &HarvestIR) -> BTreeMap<Id, Option<u64>> {
+    tool.consumes()
+        .iter()
+        .flat_map(|ty| ty.ids(ir))
+        .map(|id| (id, ir.provenance(id).map(|provenance| provenance.version)))
+        .collect()
+}
+
+/// The tools in a [Scheduler], the representation types they consume and produce, and (derived
+/// from those) which tools each tool depends on. The `Display` impl lists one tool per line:
+///
+/// ```text
+/// raw_source_to_cargo_llm: ProjectKind, RawSource -> CargoPackage (after identify_project_kind, load_raw_source)
+/// ```
+pub struct DependencyGraph {
+    tools: Vec<(
+        &'static str,
+        Vec<RepresentationType>,
+        Vec<RepresentationType>,
+    )>,
+}
+
+impl DependencyGraph {
+    /// Returns the names of the tools that produce a type that `tool` consumes.
+    pub fn dependencies(&self, tool: &str) -> BTreeSet<&'static str> {
+        let consumes = self
+            .tools
+            .iter()
+            .filter(|(name, _, _)| *name == tool)
+            .flat_map(|(_, consumes, _)| consumes);
+        consumes
+            .flat_map(|ty| {
+                self.tools
+                    .iter()
+                    .filter(move |(_, _, produces)| produces.contains(ty))
+                    .map(|(name, _, _)| *name)
+            })
+            .collect()
+    }
+}
+
+impl Display for DependencyGraph {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let list = |types: &[RepresentationType]| {
+            types
+                .iter()
+                .map(|ty| ty.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for (name, consumes, produces) in &self.tools {
+            write!(f, "{name}:")?;
+            if !consumes.is_empty() {
+                write!(f, " {}", list(consumes))?;
+            }
+            f.write_str(" ->")?;
+            if !produces.is_empty() {
+                write!(f, " {}", list(produces))?;
+            }
+            let dependencies = self.dependencies(name);
+            if !dependencies.is_empty() {
+                let dependencies: Vec<_> = dependencies.into_iter().collect();
+                write!(f, " (after {})", dependencies.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_util::MockTool;
+    use harvest_ir::Representation;
+    use harvest_ir::edit::Organizer;
+    use std::fmt::{self, Display, Formatter};
+
+    struct A;
+    impl Display for A {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("A")
+        }
+    }
+    impl Representation for A {
+        fn name(&self) -> &'static str {
+            "A"
+        }
+    }
+
+    struct B;
+    impl Display for B {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("B")
+        }
+    }
+    impl Representation for B {
+        fn name(&self) -> &'static str {
+            "B"
+        }
+    }
 
     #[test]
     fn next_invocation() {
         // Counters for the number of times the scheduler tries to run each tool invocation.
         let [mut a_count, mut b_count] = [0, 0];
+        let ir = HarvestIR::default();
         let mut scheduler = Scheduler::default();
-        scheduler.queue_invocation(MockTool::new().name("a"));
-        scheduler.queue_invocation(MockTool::new().name("b"));
+        scheduler.queue_invocation(MockTool::new().name("a").boxed());
+        scheduler.queue_invocation(MockTool::new().name("b").boxed());
         scheduler
-            .next_invocations(|t| match t.name() {
+            .next_invocations(&ir, |t| match t.name() {
                 "a" => {
                     a_count += 1;
                     NextInvocationOutcome::DontTryAgain
@@ -82,7 +282,7 @@ mod tests {
             .expect("incorrect next_invocations error");
         assert_eq!([a_count, b_count], [1, 1]);
         scheduler
-            .next_invocations(|t| match t.name() {
+            .next_invocations(&ir, |t| match t.name() {
                 "b" => {
                     b_count += 1;
                     NextInvocationOutcome::DontTryAgain
@@ -92,7 +292,99 @@ mod tests {
             .expect("incorrect next_invocations error");
         assert_eq!([a_count, b_count], [1, 2]);
         scheduler
-            .next_invocations(|t| panic!("unexpected tool invocation {}", t.name()))
+            .next_invocations(&ir, |t| panic!("unexpected tool invocation {}", t.name()))
             .expect("incorrect next_invocations error");
     }
+
+    #[test]
+    fn wait_for_inputs() {
+        let mut organizer = Organizer::default();
+        let add = |organizer: &mut Organizer, representation: Box<dyn Representation>| {
+            let mut edit = organizer.new_edit(&[].into(), &[].into()).unwrap();
+            let id = edit.add_representation(representation);
+            organizer.apply_edit(edit).unwrap();
+            id
+        };
+        let mut scheduler = Scheduler::default();
+        let [a, b] = [RepresentationType::of::<A>(), RepresentationType::of::<B>()];
+        scheduler.queue_invocation(MockTool::new().name("make_a").produces([a]).boxed());
+        scheduler.queue_invocation(
+            MockTool::new()
+                .name("a_to_b")
+                .consumes([a])
+                .produces([b])
+                .boxed(),
+        );
+        // Tools that do not declare their inputs are polled every time.
+        scheduler.queue_invocation(MockTool::new().name("undeclared").boxed());
+        assert_eq!(
+            scheduler.dependency_graph().to_string(),
+            "make_a: -> A\na_to_b: A -> B (after make_a)\nundeclared: ->\n"
+        );
+        let mut invoked = vec![];
+        let mut next = |scheduler: &mut Scheduler, ir: &HarvestIR| {
+            scheduler
+                .next_invocations(ir, |t| {
+                    invoked.push(t.name());
+                    match t.name() {
+                        "make_a" => NextInvocationOutcome::DontTryAgain,
+                        _ => NextInvocationOutcome::WaitForInputs(t),
+                    }
+                })
+                .expect("incorrect next_invocations error");
+        };
+        next(&mut scheduler, &organizer.snapshot());
+        next(&mut scheduler, &organizer.snapshot());
+        // Unrelated representations do not wake a_to_b.
+        add(&mut organizer, Box::new(B));
+        next(&mut scheduler, &organizer.snapshot());
+        let a_id = add(&mut organizer, Box::new(A));
+        next(&mut scheduler, &organizer.snapshot());
+        next(&mut scheduler, &organizer.snapshot());
+        // Rewriting a consumed representation in place wakes a_to_b as well.
+        let mut edit = organizer.new_edit(&[a_id].into(), &[].into()).unwrap();
+        edit.write_id(a_id, Box::new(A));
+        organizer.apply_edit(edit).unwrap();
+        next(&mut scheduler, &organizer.snapshot());
+        next(&mut scheduler, &organizer.snapshot());
+        assert_eq!(
+            invoked,
+            [
+                "make_a",
+                "a_to_b",
+                "undeclared",
+                "undeclared",
+                "undeclared",
+                "undeclared",
+                // Woken tools are tried after the tools that were already queued.
+                "a_to_b",
+                "undeclared",
+                "undeclared",
+                "a_to_b",
+                "undeclared",
+            ]
+        );
+        let stalled = scheduler.stalled(&HarvestIR::default());
+        assert_eq!(
+            stalled,
+            [
+                StalledTool {
+                    name: "undeclared",
+                    missing: vec![],
+                },
+                StalledTool {
+                    name: "a_to_b",
+                    missing: vec![a],
+                },
+            ]
+        );
+        assert_eq!(
+            stalled.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            [
+                "undeclared (not runnable with the final IR)",
+                "a_to_b (waiting for A)"
+            ]
+        );
+        assert_eq!(scheduler.stalled(&organizer.snapshot())[1].missing, []);
+    }
 }
@@ -1,6 +1,6 @@
 //! Place to put utilities that are only used by tests.
 
-use crate::tools::{MightWriteContext, MightWriteOutcome, RunContext, Tool};
+use crate::tools::{MightWriteContext, MightWriteOutcome, RepresentationType, RunContext, Tool};
 use std::error::Error;
 
 /// Returns a new temporary directory. Unlike the defaults in the `tempdir` and `tempfile` crates,
@@ -17,12 +17,21 @@ pub fn tempdir() -> std::io::Result<tempfile::TempDir> {
     builder.tempdir()
 }
 
+/// The closure type `MockTool` invokes when `Tool::run` is called.
+type RunFn = Box<dyn FnOnce(RunContext) -> Result<(), Box<dyn Error>> + Send>;
+
+/// The closure type `MockTool` invokes when `Tool::retry` is called.
+type RetryFn = Box<dyn Fn() -> Option<Box<dyn Tool>> + Send>;
+
 /// A tool that can be programmed to have many different behaviors, for testing code that calls
 /// `Tool`'s methods.
 pub struct MockTool {
     name: &'static str,
+    consumes: Vec<RepresentationType>,
+    produces: Vec<RepresentationType>,
     might_write: Box<dyn FnMut(MightWriteContext) -> MightWriteOutcome + Send>,
-    run: Box<dyn FnOnce(RunContext) -> Result<(), Box<dyn Error>> + Send>,
+    retry: RetryFn,
+    run: RunFn,
 }
 
 /// Builder-style API for configuring how this MockTool behaves.
@@ -30,7 +39,10 @@ pub struct MockTool {
 /// # Example
 /// ```
 /// let tool = MockTool::new()
-///     .might_write(|_| MightWriteOutcome::Runnable([].into()))
+///     .might_write(|_| MightWriteOutcome::Runnable {
+///         might_write: [].into(),
+///         might_read: [].into(),
+///     })
 ///     .run(|_| Ok(()));
 /// ```
 #[cfg_attr(miri, allow(unused))]
@@ -39,7 +51,13 @@ impl MockTool {
     pub fn new() -> MockTool {
         MockTool {
             name: "mock_tool",
-            might_write: Box::new(|_| MightWriteOutcome::Runnable([].into())),
+            consumes: vec![],
+            produces: vec![],
+            might_write: Box::new(|_| MightWriteOutcome::Runnable {
+                might_write: [].into(),
+                might_read: [].into(),
+            }),
+            retry: Box::new(|| None),
             run: Box::new(|_| Ok(())),
         }
     }
@@ -49,6 +67,12 @@ impl MockTool {
         self.into()
     }
 
+    /// Sets the return value of `Tool::consumes`.
+    pub fn consumes<I: IntoIterator<Item = RepresentationType>>(mut self, types: I) -> MockTool {
+        self.consumes = types.into_iter().collect();
+        self
+    }
+
     /// Sets a closure to be run when `Tool::might_write` is called.
     pub fn might_write<F: FnMut(MightWriteContext) -> MightWriteOutcome + Send + 'static>(
         mut self,
@@ -64,6 +88,18 @@ impl MockTool {
         self
     }
 
+    /// Sets the return value of `Tool::produces`.
+    pub fn produces<I: IntoIterator<Item = RepresentationType>>(mut self, types: I) -> MockTool {
+        self.produces = types.into_iter().collect();
+        self
+    }
+
+    /// Sets a closure to be run when `Tool::retry` is called.
+    pub fn retry<F: Fn() -> Option<Box<dyn Tool>> + Send + 'static>(mut self, f: F) -> MockTool {
+        self.retry = Box::new(f);
+        self
+    }
+
     /// Sets a closure to be run when `Tool::run` is called.
     pub fn run<F: FnOnce(RunContext) -> Result<(), Box<dyn Error>> + Send + 'static>(
         mut self,
@@ -83,6 +119,18 @@ impl Tool for MockTool {
         (self.might_write)(context)
     }
 
+    fn consumes(&self) -> Vec<RepresentationType> {
+        self.consumes.clone()
+    }
+
+    fn produces(&self) -> Vec<RepresentationType> {
+        self.produces.clone()
+    }
+
+    fn retry(&self) -> Option<Box<dyn Tool>> {
+        (self.retry)()
+    }
+
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn Error>> {
         (self.run)(context)
     }
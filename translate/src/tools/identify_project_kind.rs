@@ -1,9 +1,14 @@
 use std::fmt::Display;
 
-use harvest_ir::Representation;
+use harvest_ir::{HarvestIR, Representation, serialize::Serializable};
+use serde::{Deserialize, Serialize};
 
-use super::{MightWriteContext, MightWriteOutcome, RunContext, Tool, load_raw_source::RawSource};
+use super::{
+    MightWriteContext, MightWriteOutcome, RepresentationType, RunContext, Tool,
+    load_raw_source::RawSource,
+};
 
+#[derive(Deserialize, Serialize)]
 pub enum ProjectKind {
     Library,
     Executable,
@@ -24,6 +29,10 @@ impl Representation for ProjectKind {
     }
 }
 
+impl Serializable for ProjectKind {
+    const KIND: &'static str = "ProjectKind";
+}
+
 pub struct IdentifyProjectKind;
 
 impl Tool for IdentifyProjectKind {
@@ -33,14 +42,38 @@ impl Tool for IdentifyProjectKind {
 
     fn might_write(&mut self, context: MightWriteContext) -> MightWriteOutcome {
         // We need a raw_source to be available, but we won't write any existing IDs.
-        match context.ir.get_by_representation::<RawSource>().next() {
+        match context.ir.get_one::<RawSource>() {
             None => MightWriteOutcome::TryAgain,
-            Some(_) => MightWriteOutcome::Runnable([].into()),
+            Some(_) => MightWriteOutcome::Runnable {
+                might_write: [].into(),
+                might_read: context
+                    .ir
+                    .get_by_representation::<RawSource>()
+                    .map(|(id, _)| id)
+                    .collect(),
+            },
         }
     }
 
+    fn consumes(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<RawSource>()]
+    }
+
+    fn produces(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<ProjectKind>()]
+    }
+
+    fn retry(&self) -> Option<Box<dyn Tool>> {
+        Some(Box::new(IdentifyProjectKind))
+    }
+
+    fn already_ran(&self, ir: &HarvestIR) -> bool {
+        ir.get_one::<ProjectKind>().is_some()
+    }
+
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn std::error::Error>> {
-        for (_, repr) in context.ir_snapshot.get_by_representation::<RawSource>() {
+        for (id, repr) in context.ir_snapshot.get_by_representation::<RawSource>() {
+            context.ir_edit.record_read(id);
             if let Ok(cmakelists) = repr.dir.get_file("CMakeLists.txt") {
                 if String::from_utf8_lossy(cmakelists)
                     .lines()
@@ -1,10 +1,21 @@
 //! Lifts a source code project into a RawSource representation.
 
-use crate::tools::{MightWriteContext, MightWriteOutcome, RunContext, Tool};
-use harvest_ir::{Representation, fs::RawDir};
+use crate::cli::unknown_field_warning;
+use crate::tools::{MightWriteContext, MightWriteOutcome, RepresentationType, RunContext, Tool};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use harvest_ir::fs::{ArchiveFormat, PopulateFilter, PopulateOptions, Populated, RawDir};
+use harvest_ir::{HarvestIR, Representation, serialize::Serializable};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::fs::read_dir;
 use std::path::{Path, PathBuf};
-use tracing::info;
+use std::sync::{Arc, Mutex, PoisonError};
+use tempfile::tempdir;
+use tracing::{error, info, warn};
 
 pub struct LoadRawSource {
     directory: PathBuf,
@@ -16,6 +27,32 @@ impl LoadRawSource {
             directory: directory.into(),
         }
     }
+
+    /// Loads the input directory or archive, applying the filters in `config`.
+    fn load(&self, config: &Config) -> Result<Populated, Box<dyn std::error::Error>> {
+        // An archive is extracted into a temporary directory and loaded from there, so that the
+        // same filters apply as for a directory input.
+        let extracted;
+        let mut lazy = config.lazy;
+        let directory = match ArchiveFormat::from_path(&self.directory) {
+            Some(_) if self.directory.is_file() => {
+                // The extracted files are deleted when this returns, so they must be read now.
+                lazy = false;
+                extracted = tempdir()?;
+                RawDir::read_archive(&self.directory)?.materialize(extracted.path())?;
+                info!("Extracted archive {}", self.directory.display());
+                extracted.path()
+            }
+            _ => &self.directory,
+        };
+        let options = PopulateOptions {
+            filter: Some(config.filter(directory)?),
+            max_file_size: config.max_file_size,
+            lazy,
+            ..Default::default()
+        };
+        Ok(RawDir::populate_with(read_dir(directory)?, &options)?)
+    }
 }
 
 impl Tool for LoadRawSource {
@@ -26,24 +63,186 @@ impl Tool for LoadRawSource {
     // LoadRawSource will create a new representation, not modify an existing
     // one.
     fn might_write(&mut self, _context: MightWriteContext) -> MightWriteOutcome {
-        MightWriteOutcome::Runnable([].into())
+        MightWriteOutcome::Runnable {
+            might_write: [].into(),
+            might_read: [].into(),
+        }
+    }
+
+    fn produces(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<RawSource>()]
+    }
+
+    fn already_ran(&self, ir: &HarvestIR) -> bool {
+        ir.get_one::<RawSource>().is_some()
     }
 
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn std::error::Error>> {
-        let dir = read_dir(self.directory.clone())?;
-        let (rawdir, directories, files) = RawDir::populate_from(dir)?;
+        let populated = self.load(&context.config.tools.load_raw_source)?;
+        if !populated.skipped.is_empty() {
+            let mut skipped = String::new();
+            for (path, reason) in &populated.skipped {
+                let _ = writeln!(skipped, "{}: {reason}", path.display());
+            }
+            if let Err(error) = context.reporter.write_file("skipped", skipped) {
+                error!("Failed to write skipped entries: {error}");
+            }
+            warn!(
+                "Skipped {} entries of {} (listed in this tool run's `skipped` diagnostics file)",
+                populated.skipped.len(),
+                self.directory.display()
+            );
+        }
         info!(
-            "Loaded {directories} directories and {files} files from {}.",
-            self.directory.display()
+            "Loaded {} directories and {} files from {} (skipped {} entries).",
+            populated.directories,
+            populated.files,
+            self.directory.display(),
+            populated.skipped.len()
         );
         context
             .ir_edit
-            .add_representation(Box::new(RawSource { dir: rawdir }));
+            .add_representation(Box::new(RawSource { dir: populated.dir }));
         Ok(())
     }
 }
 
+/// Configuration for [LoadRawSource], in the `[tools.load_raw_source]` config section.
+///
+/// Glob patterns are matched against each entry's path relative to the input directory, as well
+/// as against its file name (so `build` excludes every directory named `build`).
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// Glob patterns of files to load. If empty, every file is loaded (except excluded files).
+    pub include: Vec<String>,
+
+    /// Glob patterns of files and directories to skip.
+    pub exclude: Vec<String>,
+
+    /// If true, files and directories ignored by `.gitignore` files within the input directory
+    /// are skipped.
+    pub gitignore: bool,
+
+    /// Files larger than this many bytes are skipped.
+    pub max_file_size: Option<u64>,
+
+    /// If true, file contents are read from the input directory when they are first needed,
+    /// rather than all at once when the directory is loaded. The input directory must not be
+    /// modified while harvest_translate is running. Ignored for archive inputs.
+    pub lazy: bool,
+
+    #[serde(flatten)]
+    unknown: HashMap<String, Value>,
+}
+
+impl Config {
+    pub fn validate(&self) {
+        unknown_field_warning("tools.load_raw_source", &self.unknown);
+    }
+
+    /// Returns a mock config for testing.
+    pub fn mock() -> Self {
+        Self {
+            include: vec![],
+            exclude: vec![],
+            gitignore: false,
+            max_file_size: None,
+            lazy: false,
+            unknown: HashMap::new(),
+        }
+    }
+
+    /// Builds the filter that decides which entries under `directory` should be loaded.
+    fn filter(&self, directory: &Path) -> Result<PopulateFilter, Box<dyn std::error::Error>> {
+        let include = glob_set(&self.include)?;
+        let exclude = glob_set(&self.exclude)?;
+        let gitignore = self.gitignore.then(|| GitignoreFilter::new(directory));
+        Ok(Arc::new(move |path, is_dir| {
+            let matches = |set: &GlobSet| {
+                set.is_match(path) || path.file_name().is_some_and(|n| set.is_match(n))
+            };
+            if matches(&exclude)
+                || gitignore
+                    .as_ref()
+                    .is_some_and(|g| g.is_ignored(path, is_dir))
+            {
+                return false;
+            }
+            is_dir || include.is_empty() || matches(&include)
+        }))
+    }
+}
+
+/// Compiles `patterns` into a [GlobSet].
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// Matches entries against the `.gitignore` files of the directories containing them. A
+/// directory's `.gitignore` is read the first time an entry in it is checked, so directories that
+/// are not loaded are never visited.
+struct GitignoreFilter {
+    root: PathBuf,
+    // The `.gitignore` of each directory (relative to `root`) checked so far, if it has one.
+    dirs: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl GitignoreFilter {
+    fn new(root: &Path) -> GitignoreFilter {
+        GitignoreFilter {
+            root: root.into(),
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether the entry at `path` (relative to the root) is ignored.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let full_path = self.root.join(path);
+        // A directory's `.gitignore` takes precedence over those of its parents.
+        for dir in path.ancestors().skip(1) {
+            let Some(gitignore) = self.gitignore(dir) else {
+                continue;
+            };
+            match gitignore.matched(&full_path, is_dir) {
+                Match::None => {}
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// Returns the parsed `.gitignore` of `dir` (relative to the root), if it has one.
+    fn gitignore(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut dirs = self.dirs.lock().unwrap_or_else(PoisonError::into_inner);
+        let gitignore = dirs.entry(dir.into()).or_insert_with(|| {
+            let dir = self.root.join(dir);
+            let file = dir.join(".gitignore");
+            if !file.is_file() {
+                return None;
+            }
+            let mut builder = GitignoreBuilder::new(&dir);
+            if let Some(error) = builder.add(&file) {
+                warn!("Error in {}: {error}", file.display());
+            }
+            match builder.build() {
+                Ok(gitignore) => Some(Arc::new(gitignore)),
+                Err(error) => {
+                    warn!("Ignoring {}: {error}", file.display());
+                    None
+                }
+            }
+        });
+        gitignore.clone()
+    }
+}
+
 /// A raw C project passed as input.
+#[derive(Deserialize, Serialize)]
 pub struct RawSource {
     pub dir: RawDir,
 }
@@ -63,4 +262,96 @@ impl Representation for RawSource {
     fn materialize(&self, path: &Path) -> std::io::Result<()> {
         self.dir.materialize(path)
     }
+
+    fn files(&self) -> Option<&RawDir> {
+        Some(&self.dir)
+    }
+}
+
+impl Serializable for RawSource {
+    const KIND: &'static str = "RawSource";
+    const FROM_FILES: Option<fn(RawDir) -> Self> = Some(|dir| RawSource { dir });
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+
+    #[test]
+    fn filter() {
+        let input = tempdir().unwrap();
+        let mut source = RawDir::default();
+        for path in [
+            ".git/HEAD",
+            "include/x.h",
+            "README.md",
+            "src/a.c",
+            "src/gen.c",
+            "build/out.c",
+            "vendor/gen.c",
+        ] {
+            source.set_file(path, vec![]).unwrap();
+        }
+        source
+            .set_file(".gitignore", b"gen.c\nbuild/\n".into())
+            .unwrap();
+        // A nested .gitignore takes precedence over its parent's.
+        source
+            .set_file("vendor/.gitignore", b"!gen.c\n".into())
+            .unwrap();
+        source.materialize(input.path()).unwrap();
+        let config = Config {
+            include: vec!["*.c".into(), "*.h".into()],
+            exclude: vec![".git".into()],
+            gitignore: true,
+            ..Config::mock()
+        };
+        let options = PopulateOptions {
+            filter: Some(config.filter(input.path()).unwrap()),
+            ..Default::default()
+        };
+        let populated = RawDir::populate_with(read_dir(input.path()).unwrap(), &options).unwrap();
+        let loaded = Vec::from_iter(populated.dir.files_recursive().into_iter().map(|(p, _)| p));
+        assert_eq!(
+            loaded,
+            [
+                PathBuf::from("include/x.h"),
+                "src/a.c".into(),
+                "vendor/gen.c".into()
+            ]
+        );
+        let skipped = Vec::from_iter(populated.skipped.into_iter().map(|(p, _)| p));
+        assert_eq!(
+            skipped,
+            [
+                PathBuf::from(".git"),
+                ".gitignore".into(),
+                "README.md".into(),
+                "build".into(),
+                "src/gen.c".into(),
+                "vendor/.gitignore".into()
+            ]
+        );
+    }
+
+    #[test]
+    fn archive_input() {
+        let tempdir = tempdir().unwrap();
+        let mut source = RawDir::default();
+        source.set_file("src/a.c", b"int a;\n".into()).unwrap();
+        source.set_file("src/a.o", vec![0]).unwrap();
+        let archive = tempdir.path().join("source.tar.gz");
+        source.write_archive(&archive).unwrap();
+        let config = Config {
+            exclude: vec!["*.o".into()],
+            ..Config::mock()
+        };
+        let populated = LoadRawSource::new(&archive).load(&config).unwrap();
+        assert_eq!(
+            populated.dir.files_recursive(),
+            [(PathBuf::from("src/a.c"), &b"int a;\n"[..])]
+        );
+        assert_eq!(populated.skipped.len(), 1);
+    }
 }
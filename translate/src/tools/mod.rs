@@ -5,16 +5,26 @@ pub mod load_raw_source;
 pub mod raw_source_to_cargo_llm;
 pub mod try_cargo_build;
 
-use crate::{cli::unknown_field_warning, diagnostics::ToolReporter};
-use harvest_ir::{Edit, HarvestIR, Id};
+use crate::cli::{Config, unknown_field_warning};
+use crate::diagnostics::ToolReporter;
+use harvest_ir::invariant::{AtMostOne, IrInvariant};
+use harvest_ir::{Edit, HarvestIR, Id, Representation, serialize::Registry};
+use identify_project_kind::IdentifyProjectKind;
+use load_raw_source::LoadRawSource;
+use raw_source_to_cargo_llm::RawSourceToCargoLlm;
 use serde::Deserialize;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::any::{TypeId, type_name};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::sync::Arc;
+use thiserror::Error;
+use try_cargo_build::TryCargoBuild;
 
 /// Combined configuration for all Tools in this crate.
 #[derive(Debug, Deserialize)]
 pub struct ToolConfigs {
+    pub load_raw_source: load_raw_source::Config,
     pub raw_source_to_cargo_llm: raw_source_to_cargo_llm::Config,
 
     #[serde(flatten)]
@@ -24,18 +34,126 @@ pub struct ToolConfigs {
 impl ToolConfigs {
     pub fn validate(&self) {
         unknown_field_warning("tools", &self.unknown);
+        self.load_raw_source.validate();
         self.raw_source_to_cargo_llm.validate();
     }
 
     /// Returns a mock config for testing.
     pub fn mock() -> Self {
         Self {
+            load_raw_source: load_raw_source::Config::mock(),
             raw_source_to_cargo_llm: raw_source_to_cargo_llm::Config::mock(),
             unknown: HashMap::new(),
         }
     }
 }
 
+/// Returns a [Registry] containing the serializable representations produced by this crate's
+/// tools. Pass this to [HarvestIR::save] and [HarvestIR::load].
+pub fn representations() -> Registry {
+    let mut registry = Registry::default();
+    registry
+        .register::<load_raw_source::RawSource>()
+        .register::<identify_project_kind::ProjectKind>()
+        .register::<raw_source_to_cargo_llm::CargoPackage>()
+        .register::<try_cargo_build::CargoBuildResult>();
+    registry
+}
+
+/// Returns a [ToolRegistry] containing this crate's tools, from which `transpile` constructs the
+/// pipeline selected by `config`.
+pub fn registry(config: &Config) -> ToolRegistry<'_> {
+    let mut registry = ToolRegistry::new(config);
+    registry
+        .register(|config| Box::new(LoadRawSource::new(&config.input)))
+        .register(|_| Box::new(IdentifyProjectKind))
+        .register(|_| Box::new(RawSourceToCargoLlm))
+        .register(|_| Box::new(TryCargoBuild));
+    registry
+}
+
+/// Constructs an invocation of a tool, given the configuration for this run.
+pub type ToolConstructor = fn(&Config) -> Box<dyn Tool>;
+
+/// The tools that can be named in a pipeline (see [Config::pipeline]), keyed by [Tool::name], and
+/// the configuration to construct them with.
+pub struct ToolRegistry<'c> {
+    config: &'c Config,
+    constructors: BTreeMap<&'static str, ToolConstructor>,
+}
+
+impl<'c> ToolRegistry<'c> {
+    /// Returns an empty registry that constructs tools with `config`.
+    pub fn new(config: &'c Config) -> Self {
+        Self {
+            config,
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a tool under the [Tool::name] of the invocation `constructor` returns, which is
+    /// called once to find it (so constructors should be cheap and have no side effects). Panics
+    /// if a tool with this name is already registered.
+    pub fn register(&mut self, constructor: ToolConstructor) -> &mut Self {
+        let name = constructor(self.config).name();
+        if self.constructors.insert(name, constructor).is_some() {
+            panic!("tool {name} registered twice");
+        }
+        self
+    }
+
+    /// Returns the names of the registered tools, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        self.constructors.keys().copied()
+    }
+
+    /// Constructs an invocation of the tool named `name`, or returns `None` if there is no such
+    /// tool.
+    pub fn construct(&self, name: &str) -> Option<Box<dyn Tool>> {
+        self.constructors
+            .get(name)
+            .map(|constructor| constructor(self.config))
+    }
+
+    /// Constructs an invocation of each tool in the pipeline selected by the config's `pipeline`.
+    pub fn pipeline(&self) -> Result<Vec<Box<dyn Tool>>, PipelineError> {
+        let config = self.config;
+        let pipeline = config
+            .pipelines
+            .get(&config.pipeline)
+            .ok_or_else(|| PipelineError::UnknownPipeline(config.pipeline.clone()))?;
+        pipeline
+            .tools
+            .iter()
+            .map(|name| {
+                self.construct(name)
+                    .ok_or_else(|| PipelineError::UnknownTool {
+                        pipeline: config.pipeline.clone(),
+                        tool: name.clone(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Error returned by [ToolRegistry::pipeline].
+#[derive(Debug, Error, PartialEq)]
+pub enum PipelineError {
+    #[error("unknown pipeline {0:?}")]
+    UnknownPipeline(String),
+    #[error("pipeline {pipeline:?} contains unknown tool {tool:?}")]
+    UnknownTool { pipeline: String, tool: String },
+}
+
+/// Returns the IR invariants that this crate's tools rely on. `transpile` checks these after every
+/// tool run.
+pub fn invariants() -> Vec<Box<dyn IrInvariant>> {
+    vec![
+        Box::new(AtMostOne::<raw_source_to_cargo_llm::CargoPackage>::new()),
+        Box::new(raw_source_to_cargo_llm::CargoPackageHasManifest),
+    ]
+}
+
 /// Trait implemented by each tool. Used by the scheduler to decide what tools
 /// to run and to manage those tools.
 ///
@@ -53,13 +171,50 @@ pub trait Tool: Send + 'static {
     fn name(&self) -> &'static str;
 
     /// Returns an indication of whether the tool can be run now, and if it can be run, which IDs
-    /// it might write. The IDs returned may depend on the tool constructor's arguments as well as
-    /// the contents of `context.ir`.
+    /// it might write and read. The IDs returned may depend on the tool constructor's arguments as
+    /// well as the contents of `context.ir`.
     ///
     /// might_write may be called multiple times before the tool is run. Returning
     /// `MightWriteOutcome::Runnable` does not guarantee that this tool will be executed.
     fn might_write(&mut self, context: MightWriteContext) -> MightWriteOutcome;
 
+    /// Returns the types of representation this tool reads. When `might_write` returns
+    /// `TryAgain`, the scheduler does not ask again until a new representation of one of these
+    /// types appears in the IR.
+    ///
+    /// The default implementation returns no types, in which case the scheduler asks again every
+    /// time it looks for tools to run.
+    fn consumes(&self) -> Vec<RepresentationType> {
+        vec![]
+    }
+
+    /// Returns the types of representation this tool might add to the IR. This is only used to
+    /// describe the pipeline (in the diagnostics directory's `pipeline` file), and does not
+    /// restrict what the tool may write.
+    fn produces(&self) -> Vec<RepresentationType> {
+        vec![]
+    }
+
+    /// Returns `true` if `ir` already contains this tool invocation's outputs. When resuming from
+    /// a previous run's IR, `transpile` does not queue tools that have already run (unless the
+    /// user asked for them to be re-run).
+    ///
+    /// The default implementation returns `false`, so tools that do not override it are always
+    /// re-run when resuming.
+    fn already_ran(&self, _ir: &HarvestIR) -> bool {
+        false
+    }
+
+    /// Returns a new invocation equivalent to this one, to be queued if this invocation's edit is
+    /// rejected as stale (i.e. one of the representations it might have read was modified while
+    /// it was running). This is called before `run`.
+    ///
+    /// The default implementation returns `None`, so tools that do not override it are not
+    /// retried.
+    fn retry(&self) -> Option<Box<dyn Tool>> {
+        None
+    }
+
     /// Runs the tool logic. IR access and edits are made using `context`.
     ///
     /// If `Ok` is returned the changes will be applied to the IR, and if `Err`
@@ -67,6 +222,62 @@ pub trait Tool: Send + 'static {
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn std::error::Error>>;
 }
 
+/// A type of [Representation], as declared by [Tool::consumes] and [Tool::produces].
+#[derive(Clone, Copy)]
+pub struct RepresentationType {
+    type_id: TypeId,
+    name: &'static str,
+    ids: fn(&HarvestIR) -> BTreeSet<Id>,
+}
+
+impl RepresentationType {
+    /// Returns the `RepresentationType` for `R`.
+    pub fn of<R: Representation>() -> RepresentationType {
+        fn ids<R: Representation>(ir: &HarvestIR) -> BTreeSet<Id> {
+            ir.get_by_representation::<R>().map(|(id, _)| id).collect()
+        }
+        let name = type_name::<R>();
+        RepresentationType {
+            type_id: TypeId::of::<R>(),
+            // Strip the module path (but not generic parameters, which may contain `::`).
+            name: name[..name.find('<').unwrap_or(name.len())]
+                .rsplit_once("::")
+                .map_or(name, |(path, _)| &name[path.len() + 2..]),
+            ids: ids::<R>,
+        }
+    }
+
+    /// The name of the representation's Rust type, without its module path.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the IDs of the representations of this type in `ir`.
+    pub fn ids(&self, ir: &HarvestIR) -> BTreeSet<Id> {
+        (self.ids)(ir)
+    }
+}
+
+impl Debug for RepresentationType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl Display for RepresentationType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl PartialEq for RepresentationType {
+    fn eq(&self, other: &RepresentationType) -> bool {
+        self.type_id == other.type_id
+    }
+}
+
+impl Eq for RepresentationType {}
+
 /// Context passed to `Tool::might_write`. This is a struct so that new values may be added without
 /// having to edit every Tool impl.
 #[non_exhaustive]
@@ -78,12 +289,16 @@ pub struct MightWriteContext<'a> {
 /// Result of a `Tool::might_write` execution.
 pub enum MightWriteOutcome {
     /// This tool is not and will not be runnable. Tells the scheduler to discard the tool.
-    #[allow(unused)] // TODO: Remove when we have a tool that returns this.
     NotRunnable,
 
-    /// This tool is runnable. The set of IDs returned are the IDs for representations in the
-    /// HarvestIR that the tool might write if it is run.
-    Runnable(HashSet<Id>),
+    /// This tool is runnable.
+    Runnable {
+        /// IDs of representations in the HarvestIR that the tool might write if it is run.
+        might_write: HashSet<Id>,
+        /// IDs of representations in the HarvestIR that the tool might read if it is run. If any of
+        /// these are modified while the tool is running, the tool's edit is rejected as stale.
+        might_read: HashSet<Id>,
+    },
 
     /// The tool cannot be run now (e.g. it might need input data that it did not find in the IR),
     /// but it might become runnable in the future so the scheduler should try again later.
@@ -105,9 +320,85 @@ pub struct RunContext<'a> {
     pub ir_snapshot: Arc<HarvestIR>,
 
     /// Configuration for the current harvest_translate run.
-    pub config: Arc<crate::cli::Config>,
+    pub config: Arc<Config>,
 
     /// Handle through which to report diagnostics and create temporary directories (which live
     /// inside the diagnostics directory).
     pub reporter: ToolReporter,
+
+    // Tool invocations suggested by this tool (see `suggest`).
+    pub(crate) suggestions: &'a mut Vec<Box<dyn Tool>>,
+}
+
+impl RunContext<'_> {
+    /// Suggests a follow-up tool invocation (e.g. `TryCargoBuild` after a tool modifies the
+    /// `CargoPackage`). Suggested invocations are queued in the scheduler once this tool's edit
+    /// has been applied, and are discarded if the tool fails or its edit is rejected.
+    pub fn suggest(&mut self, tool: Box<dyn Tool>) {
+        self.suggestions.push(tool);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::PipelineConfig;
+
+    #[test]
+    fn registry() {
+        let config = Config::mock();
+        let registry = super::registry(&config);
+        assert_eq!(
+            Vec::from_iter(registry.names()),
+            [
+                "identify_project_kind",
+                "load_raw_source",
+                "raw_source_to_cargo_llm",
+                "try_cargo_build"
+            ]
+        );
+        for name in registry.names() {
+            assert_eq!(registry.construct(name).unwrap().name(), name);
+        }
+        assert!(registry.construct("unknown").is_none());
+        let config = |pipeline: &str| {
+            let mut config = Config::mock();
+            config.pipeline = pipeline.into();
+            config.pipelines = [
+                (
+                    "a".into(),
+                    PipelineConfig::new(vec!["try_cargo_build".into()]),
+                ),
+                (
+                    "b".into(),
+                    PipelineConfig::new(vec!["load_raw_source".into(), "unknown".into()]),
+                ),
+            ]
+            .into();
+            config
+        };
+        let tools = super::registry(&config("a")).pipeline().unwrap();
+        assert_eq!(
+            Vec::from_iter(tools.iter().map(|t| t.name())),
+            ["try_cargo_build"]
+        );
+        assert_eq!(
+            super::registry(&config("b")).pipeline().err(),
+            Some(PipelineError::UnknownTool {
+                pipeline: "b".into(),
+                tool: "unknown".into(),
+            })
+        );
+        assert_eq!(
+            super::registry(&config("c")).pipeline().err(),
+            Some(PipelineError::UnknownPipeline("c".into()))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn duplicate_tool() {
+        let config = Config::mock();
+        super::registry(&config).register(|_| Box::new(TryCargoBuild));
+    }
 }
@@ -2,9 +2,11 @@
 //! an LLM via the `llm` crate.
 
 use crate::cli::unknown_field_warning;
-use crate::load_raw_source::RawSource;
-use crate::tools::{MightWriteContext, MightWriteOutcome, RunContext, Tool};
-use harvest_ir::{Representation, fs::RawDir};
+use crate::tools::load_raw_source::RawSource;
+use crate::tools::{MightWriteContext, MightWriteOutcome, RepresentationType, RunContext, Tool};
+use harvest_ir::fs::{ArchiveFormat, RawDir};
+use harvest_ir::invariant::IrInvariant;
+use harvest_ir::{HarvestIR, NotUnique, Representation, serialize::Serializable};
 use llm::builder::{LLMBackend, LLMBuilder};
 use llm::chat::{ChatMessage, StructuredOutputFormat};
 use serde::{Deserialize, Serialize};
@@ -30,32 +32,50 @@ impl Tool for RawSourceToCargoLlm {
     }
 
     fn might_write(&mut self, context: MightWriteContext) -> MightWriteOutcome {
-        // We need a raw_source to be available, but we won't write any existing IDs.
+        // We need a raw_source to be available, but we won't write any existing IDs. If there are
+        // several, we cannot tell which one to translate.
         match (
-            context.ir.get_by_representation::<ProjectKind>().next(),
-            context.ir.get_by_representation::<RawSource>().next(),
+            context.ir.expect_unique::<ProjectKind>(),
+            context.ir.expect_unique::<RawSource>(),
         ) {
-            (Some(_), Some(_)) => MightWriteOutcome::Runnable([].into()),
+            (Ok((project_kind, _)), Ok((raw_source, _))) => MightWriteOutcome::Runnable {
+                might_write: [].into(),
+                might_read: [project_kind, raw_source].into(),
+            },
+            (Err(NotUnique::Multiple(..)), _) | (_, Err(NotUnique::Multiple(..))) => {
+                MightWriteOutcome::NotRunnable
+            }
             _ => MightWriteOutcome::TryAgain,
         }
     }
 
+    fn consumes(&self) -> Vec<RepresentationType> {
+        vec![
+            RepresentationType::of::<ProjectKind>(),
+            RepresentationType::of::<RawSource>(),
+        ]
+    }
+
+    fn produces(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<CargoPackage>()]
+    }
+
+    fn retry(&self) -> Option<Box<dyn Tool>> {
+        Some(Box::new(RawSourceToCargoLlm))
+    }
+
+    fn already_ran(&self, ir: &HarvestIR) -> bool {
+        ir.get_one::<CargoPackage>().is_some()
+    }
+
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn std::error::Error>> {
         let config = &context.config.tools.raw_source_to_cargo_llm;
         debug!("LLM Configuration {config:?}");
-        let in_dir = &context
-            .ir_snapshot
-            .get_by_representation::<RawSource>()
-            .next()
-            .unwrap()
-            .1
-            .dir;
-        let project_kind = context
-            .ir_snapshot
-            .get_by_representation::<ProjectKind>()
-            .next()
-            .unwrap()
-            .1;
+        let (raw_source_id, raw_source) = context.ir_snapshot.expect_unique::<RawSource>()?;
+        let (project_kind_id, project_kind) = context.ir_snapshot.expect_unique::<ProjectKind>()?;
+        let in_dir = &raw_source.dir;
+        context.ir_edit.record_read(raw_source_id);
+        context.ir_edit.record_read(project_kind_id);
 
         // Use the llm crate to connect to Ollama.
 
@@ -105,7 +125,7 @@ impl Tool for RawSourceToCargoLlm {
         // Assemble the Ollama request.
         let mut request = vec!["Please translate the following C project into a Rust project including Cargo manifest:".into()];
         request.push(
-            serde_json::json!({"files": (&in_dir.files_recursive().iter().map(|(path, contents)| {
+            serde_json::json!({"files": (&in_dir.try_files_recursive()?.iter().map(|(path, contents)| {
                 OutputFile {
                     path: path.clone(),
                     contents: String::from_utf8_lossy(contents).into(),
@@ -154,6 +174,7 @@ impl Tool for RawSourceToCargoLlm {
 }
 
 /// A cargo project representation (Cargo.toml, src/, etc).
+#[derive(Deserialize, Serialize)]
 pub struct CargoPackage {
     pub dir: RawDir,
 }
@@ -170,8 +191,43 @@ impl Representation for CargoPackage {
         "CargoPackage"
     }
 
+    /// Materializes the package as a directory tree or, if `path` has an archive extension (see
+    /// [ArchiveFormat::from_path]), as a single archive file.
     fn materialize(&self, path: &Path) -> std::io::Result<()> {
-        self.dir.materialize(path)
+        match ArchiveFormat::from_path(path) {
+            None => self.dir.materialize(path),
+            Some(_) => self.dir.write_archive(path).map_err(std::io::Error::other),
+        }
+    }
+
+    fn files(&self) -> Option<&RawDir> {
+        Some(&self.dir)
+    }
+}
+
+impl Serializable for CargoPackage {
+    const KIND: &'static str = "CargoPackage";
+    const FROM_FILES: Option<fn(RawDir) -> Self> = Some(|dir| CargoPackage { dir });
+}
+
+/// Invariant that every [CargoPackage] contains a `Cargo.toml` at its root.
+pub struct CargoPackageHasManifest;
+
+impl IrInvariant for CargoPackageHasManifest {
+    fn name(&self) -> String {
+        "cargo_package_has_manifest".into()
+    }
+
+    fn check(&self, ir: &HarvestIR) -> Result<(), String> {
+        let missing: Vec<_> = ir
+            .get_by_representation::<CargoPackage>()
+            .filter(|(_, package)| package.dir.get_file("Cargo.toml").is_err())
+            .map(|(id, _)| id.to_string())
+            .collect();
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(format!("no Cargo.toml in {}", missing.join(", "))),
+        }
     }
 }
 
@@ -229,3 +285,29 @@ struct OutputFile {
     contents: String,
     path: PathBuf,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn might_write() {
+        let might_write =
+            |ir: &HarvestIR| RawSourceToCargoLlm.might_write(MightWriteContext { ir });
+        let mut ir = HarvestIR::default();
+        ir.add_representation(Box::new(ProjectKind::Executable));
+        assert!(matches!(might_write(&ir), MightWriteOutcome::TryAgain));
+        ir.add_representation(Box::new(RawSource {
+            dir: RawDir::default(),
+        }));
+        assert!(matches!(
+            might_write(&ir),
+            MightWriteOutcome::Runnable { .. }
+        ));
+        // With several sources, there is no way to tell which one to translate.
+        ir.add_representation(Box::new(RawSource {
+            dir: RawDir::default(),
+        }));
+        assert!(matches!(might_write(&ir), MightWriteOutcome::NotRunnable));
+    }
+}
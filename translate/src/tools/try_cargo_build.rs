@@ -1,8 +1,9 @@
 //! Checks if a generated Rust project builds by materializing
 //! it to a tempdir and running `cargo build --release`.
 use crate::tools::raw_source_to_cargo_llm::CargoPackage;
-use crate::tools::{MightWriteContext, MightWriteOutcome, RunContext, Tool};
-use harvest_ir::{HarvestIR, Representation, fs::RawDir};
+use crate::tools::{MightWriteContext, MightWriteOutcome, RepresentationType, RunContext, Tool};
+use harvest_ir::{HarvestIR, Id, NotUnique, Representation, fs::RawDir, serialize::Serializable};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tracing::info;
@@ -81,21 +82,9 @@ fn try_cargo_build(project_path: &PathBuf) -> Result<BuildResult, Box<dyn std::e
 /// Returns the CargoPackage representation in IR.
 /// If there is not exactly 1 CargoPackage representation,
 /// return an error.
-fn raw_cargo_package(ir: &HarvestIR) -> Result<&RawDir, Box<dyn std::error::Error>> {
-    let cargo_packages: Vec<&RawDir> = ir
-        .get_by_representation::<CargoPackage>()
-        .map(|(_, r)| &r.dir)
-        .collect();
-
-    match cargo_packages.len() {
-        0 => Err("No CargoPackage representation found in IR".into()),
-        1 => Ok(cargo_packages[0]),
-        n => Err(format!(
-            "Found {} CargoPackage representations, expected at most 1",
-            n
-        )
-        .into()),
-    }
+fn raw_cargo_package(ir: &HarvestIR) -> Result<(Id, &RawDir), NotUnique> {
+    ir.expect_unique::<CargoPackage>()
+        .map(|(id, r)| (id, &r.dir))
 }
 
 impl Tool for TryCargoBuild {
@@ -107,13 +96,33 @@ impl Tool for TryCargoBuild {
         // We need a cargo_package to be available, but we won't write any existing IDs.
         match raw_cargo_package(context.ir) {
             Err(_) => MightWriteOutcome::TryAgain,
-            Ok(_) => MightWriteOutcome::Runnable([].into()),
+            Ok((id, _)) => MightWriteOutcome::Runnable {
+                might_write: [].into(),
+                might_read: [id].into(),
+            },
         }
     }
 
+    fn consumes(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<CargoPackage>()]
+    }
+
+    fn produces(&self) -> Vec<RepresentationType> {
+        vec![RepresentationType::of::<CargoBuildResult>()]
+    }
+
+    fn retry(&self) -> Option<Box<dyn Tool>> {
+        Some(Box::new(TryCargoBuild))
+    }
+
+    fn already_ran(&self, ir: &HarvestIR) -> bool {
+        ir.get_one::<CargoBuildResult>().is_some()
+    }
+
     fn run(self: Box<Self>, context: RunContext) -> Result<(), Box<dyn std::error::Error>> {
         // Get cargo package representation
-        let cargo_package = raw_cargo_package(&context.ir_snapshot)?;
+        let (cargo_package_id, cargo_package) = raw_cargo_package(&context.ir_snapshot)?;
+        context.ir_edit.record_read(cargo_package_id);
         let output_path = context.config.output.clone();
         cargo_package.materialize(&output_path)?;
 
@@ -131,6 +140,7 @@ impl Tool for TryCargoBuild {
 }
 
 /// A Representation that contains the results of running `cargo build`.
+#[derive(Deserialize, Serialize)]
 pub struct CargoBuildResult {
     pub result: Result<Vec<PathBuf>, String>,
 }
@@ -159,3 +169,7 @@ impl Representation for CargoBuildResult {
         Ok(())
     }
 }
+
+impl Serializable for CargoBuildResult {
+    const KIND: &'static str = "CargoBuildResult";
+}